@@ -0,0 +1,237 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::clock::CLOCKS_PER_BEAT;
+
+/// Degrees run up from the note played when there's no chord to arpeggiate
+pub const ARP_SCALE_NOTES: usize = 4;
+pub const DEFAULT_BPM: f32 = 120.0;
+pub const MAX_STEPS_PER_BEAT: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArpPattern {
+    #[default]
+    Up,
+    Down,
+    /// Doesn't repeat the top and bottom notes
+    UpDown,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpTempo {
+    /// Beats per minute
+    Internal(f32),
+    MidiClock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arp {
+    pub pattern: ArpPattern,
+    pub steps_per_beat: u8,
+    pub tempo: ArpTempo,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Arp {
+            pattern: ArpPattern::default(),
+            steps_per_beat: 4,
+            tempo: ArpTempo::Internal(DEFAULT_BPM),
+        }
+    }
+}
+
+/// Where an arpeggio has got to
+pub struct Arpeggiator {
+    step: usize,
+    next_step_at: Option<Instant>,
+    last_tick: Option<u64>,
+    /// xorshift state for `ArpPattern::Random`
+    seed: u32,
+}
+
+impl Arpeggiator {
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_nanos());
+        Arpeggiator {
+            step: 0,
+            next_step_at: None,
+            last_tick: None,
+            // xorshift never leaves 0
+            seed: nanos | 1,
+        }
+    }
+
+    /// The first step is due straight away
+    pub fn restart(&mut self) {
+        self.step = 0;
+        self.next_step_at = None;
+        self.last_tick = None;
+    }
+
+    /// The number of the step that's due if there is one, `clock_ticks` is the MIDI clock's
+    /// count if it's open
+    pub fn due(&mut self, arp: &Arp, now: Instant, clock_ticks: Option<u64>) -> Option<usize> {
+        let steps_per_beat = arp.steps_per_beat.clamp(1, MAX_STEPS_PER_BEAT);
+        let due = match arp.tempo {
+            ArpTempo::Internal(bpm) => {
+                let interval = Duration::from_secs_f32(60.0 / bpm.max(1.0) / steps_per_beat as f32);
+                match self.next_step_at {
+                    Some(next_step_at) if now < next_step_at => false,
+                    // after a stall start counting again from now rather than catching up
+                    Some(next_step_at) if now - next_step_at < interval => {
+                        self.next_step_at = Some(next_step_at + interval);
+                        true
+                    }
+                    _ => {
+                        self.next_step_at = Some(now + interval);
+                        true
+                    }
+                }
+            }
+            ArpTempo::MidiClock => {
+                let ticks = clock_ticks?;
+                let ticks_per_step = (CLOCKS_PER_BEAT / steps_per_beat as u64).max(1);
+                match self.last_tick {
+                    // restarted by the sequencer
+                    Some(last_tick) if ticks < last_tick => {
+                        self.last_tick = Some(ticks);
+                        true
+                    }
+                    Some(last_tick) if ticks - last_tick < ticks_per_step => false,
+                    _ => {
+                        self.last_tick = Some(ticks - ticks % ticks_per_step);
+                        true
+                    }
+                }
+            }
+        };
+        if !due {
+            return None;
+        }
+        self.step += 1;
+        Some(self.step - 1)
+    }
+
+    /// Which of `len` notes, sorted low to high, `step` plays
+    pub fn pick(&mut self, pattern: ArpPattern, step: usize, len: usize) -> usize {
+        if len <= 1 {
+            return 0;
+        }
+        match pattern {
+            ArpPattern::Up => step % len,
+            ArpPattern::Down => len - 1 - step % len,
+            ArpPattern::UpDown => {
+                let period = 2 * len - 2;
+                let i = step % period;
+                if i < len {
+                    i
+                } else {
+                    period - i
+                }
+            }
+            ArpPattern::Random => {
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 17;
+                self.seed ^= self.seed << 5;
+                self.seed as usize % len
+            }
+        }
+    }
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_arp(steps_per_beat: u8) -> Arp {
+        Arp {
+            steps_per_beat,
+            tempo: ArpTempo::MidiClock,
+            ..Arp::default()
+        }
+    }
+
+    #[test]
+    fn up_down_doesnt_repeat_the_ends() {
+        let mut arpeggiator = Arpeggiator::new();
+        let picks: Vec<usize> = (0..12)
+            .map(|step| arpeggiator.pick(ArpPattern::UpDown, step, 4))
+            .collect();
+        assert_eq!(picks, [0, 1, 2, 3, 2, 1, 0, 1, 2, 3, 2, 1]);
+        let picks: Vec<usize> = (0..4)
+            .map(|step| arpeggiator.pick(ArpPattern::UpDown, step, 2))
+            .collect();
+        assert_eq!(picks, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn single_note_always_plays() {
+        let mut arpeggiator = Arpeggiator::new();
+        for pattern in [
+            ArpPattern::Up,
+            ArpPattern::Down,
+            ArpPattern::UpDown,
+            ArpPattern::Random,
+        ] {
+            for step in 0..5 {
+                assert_eq!(arpeggiator.pick(pattern, step, 1), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn clock_steps_land_on_the_grid() {
+        let now = Instant::now();
+        for steps_per_beat in [3, 6, 8] {
+            let ticks_per_step = CLOCKS_PER_BEAT / steps_per_beat as u64;
+            let arp = clock_arp(steps_per_beat);
+            let mut arpeggiator = Arpeggiator::new();
+            let due: Vec<u64> = (0..2 * CLOCKS_PER_BEAT)
+                .filter(|&ticks| arpeggiator.due(&arp, now, Some(ticks)).is_some())
+                .collect();
+            let grid: Vec<u64> = (0..2 * steps_per_beat as u64)
+                .map(|step| step * ticks_per_step)
+                .collect();
+            assert_eq!(due, grid, "{steps_per_beat} steps per beat");
+        }
+    }
+
+    #[test]
+    fn clock_joined_mid_step_keeps_to_the_grid() {
+        let now = Instant::now();
+        let arp = clock_arp(3);
+        let mut arpeggiator = Arpeggiator::new();
+        assert_eq!(arpeggiator.due(&arp, now, Some(5)), Some(0));
+        assert_eq!(arpeggiator.due(&arp, now, Some(7)), None);
+        assert_eq!(arpeggiator.due(&arp, now, Some(8)), Some(1));
+        // the sequencer starting again restarts the count of ticks
+        assert_eq!(arpeggiator.due(&arp, now, Some(0)), Some(2));
+    }
+
+    #[test]
+    fn no_clock_no_steps() {
+        let mut arpeggiator = Arpeggiator::new();
+        assert_eq!(arpeggiator.due(&clock_arp(4), Instant::now(), None), None);
+    }
+
+    #[test]
+    fn internal_tempo_steps_at_its_interval() {
+        let start = Instant::now();
+        let arp = Arp::default();
+        let mut arpeggiator = Arpeggiator::new();
+        // 120 bpm in 16ths
+        assert_eq!(arpeggiator.due(&arp, start, None), Some(0));
+        let step = Duration::from_millis(125);
+        assert_eq!(arpeggiator.due(&arp, start + step / 2, None), None);
+        assert_eq!(arpeggiator.due(&arp, start + step, None), Some(1));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use midir::{os::unix::VirtualInput, MidiInput, MidiInputConnection};
+
+use crate::error::Result;
+
+const CLOCK_MSG: u8 = 0xF8;
+const START_MSG: u8 = 0xFA;
+const CONTINUE_MSG: u8 = 0xFB;
+const STOP_MSG: u8 = 0xFC;
+/// MIDI clock ticks per quarter note
+pub const CLOCKS_PER_BEAT: u64 = 24;
+/// Longer than a tick at any tempo worth playing along to
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(250);
+const NEVER: u64 = u64::MAX;
+
+/// Lines notes can be quantized to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grid {
+    Eighth,
+    EighthTriplet,
+    Sixteenth,
+    SixteenthTriplet,
+}
+
+impl Grid {
+    pub fn ticks(self) -> u64 {
+        match self {
+            Grid::Eighth => CLOCKS_PER_BEAT / 2,
+            Grid::EighthTriplet => CLOCKS_PER_BEAT / 3,
+            Grid::Sixteenth => CLOCKS_PER_BEAT / 4,
+            Grid::SixteenthTriplet => CLOCKS_PER_BEAT / 6,
+        }
+    }
+}
+
+/// Where the sequencer's got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPos {
+    /// Since the last start message, so counts are on the sequencer's grid
+    pub ticks: u64,
+    pub running: bool,
+}
+
+/// Follows the clock, start, continue and stop messages sent to a virtual input port
+pub struct MidiClock {
+    ticks: Arc<AtomicU64>,
+    stopped: Arc<AtomicBool>,
+    /// Milliseconds after `opened`, `NEVER` until the first tick
+    last_tick: Arc<AtomicU64>,
+    opened: Instant,
+    _conn_in: MidiInputConnection<()>,
+}
+
+impl MidiClock {
+    pub fn open(name: &str) -> Result<Self> {
+        let ticks = Arc::new(AtomicU64::new(0));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let last_tick = Arc::new(AtomicU64::new(NEVER));
+        let opened = Instant::now();
+        let (counted, stop, ticked) = (ticks.clone(), stopped.clone(), last_tick.clone());
+        let conn_in = MidiInput::new("Theramin midi in")?.create_virtual(
+            name,
+            move |_, msg, _| match msg.first() {
+                // ticks only count while running so continue picks up where stop left off
+                Some(&CLOCK_MSG) if !stop.load(Ordering::Acquire) => {
+                    counted.fetch_add(1, Ordering::AcqRel);
+                    ticked.store(opened.elapsed().as_millis() as u64, Ordering::Release);
+                }
+                Some(&START_MSG) => {
+                    counted.store(0, Ordering::Release);
+                    stop.store(false, Ordering::Release);
+                }
+                Some(&CONTINUE_MSG) => stop.store(false, Ordering::Release),
+                Some(&STOP_MSG) => stop.store(true, Ordering::Release),
+                _ => (),
+            },
+            (),
+        )?;
+        Ok(MidiClock {
+            ticks,
+            stopped,
+            last_tick,
+            opened,
+            _conn_in: conn_in,
+        })
+    }
+
+    /// Only running while ticks are arriving, so clocks that never send start count too
+    pub fn pos(&self) -> ClockPos {
+        let last_tick = self.last_tick.load(Ordering::Acquire);
+        let since_tick = (self.opened.elapsed().as_millis() as u64).saturating_sub(last_tick);
+        ClockPos {
+            ticks: self.ticks.load(Ordering::Acquire),
+            running: !self.stopped.load(Ordering::Acquire)
+                && last_tick != NEVER
+                && since_tick < CLOCK_TIMEOUT.as_millis() as u64,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use tokio::sync::mpsc::error::TrySendError;
+
+pub type Result<T> = std::result::Result<T, TheraminError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TheraminError {
+    ManyMouseInit,
+    ManyMouseInUse,
+    MidiInit(String),
+    MidiPortInfo(String),
+    MidiConnect(String),
+    MidiSend(String),
+    MsgChannelFull,
+    MsgChannelClosed,
+    CursorGrab(String),
+    NoMultitouch(String),
+    Tuning(String),
+    /// Semitones either way the bend was clamped to
+    BendRange(u8),
+    Io(String),
+}
+
+impl fmt::Display for TheraminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TheraminError::ManyMouseInit => write!(f, "ManyMouse couldn't initialize"),
+            TheraminError::ManyMouseInUse => write!(f, "ManyMouse is already initialized"),
+            TheraminError::MidiInit(e) => write!(f, "couldn't initialize midi output: {}", e),
+            TheraminError::MidiPortInfo(e) => write!(f, "couldn't read midi port info: {}", e),
+            TheraminError::MidiConnect(e) => write!(f, "couldn't connect midi output: {}", e),
+            TheraminError::MidiSend(e) => write!(f, "couldn't send midi message: {}", e),
+            TheraminError::MsgChannelFull => write!(f, "theramin routine is busy, message dropped"),
+            TheraminError::MsgChannelClosed => write!(f, "theramin routine has stopped"),
+            TheraminError::CursorGrab(e) => write!(f, "couldn't grab cursor: {}", e),
+            TheraminError::NoMultitouch(name) => {
+                write!(f, "no multitouch device found for {}", name)
+            }
+            TheraminError::Tuning(e) => write!(f, "couldn't load tuning: {}", e),
+            TheraminError::BendRange(range) => write!(
+                f,
+                "pitch bend past the synth's range of {} semitones, notes are out of tune",
+                range
+            ),
+            TheraminError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TheraminError {}
+
+impl From<std::io::Error> for TheraminError {
+    fn from(e: std::io::Error) -> Self {
+        TheraminError::Io(e.to_string())
+    }
+}
+
+impl From<midir::InitError> for TheraminError {
+    fn from(e: midir::InitError) -> Self {
+        TheraminError::MidiInit(e.to_string())
+    }
+}
+
+impl From<midir::PortInfoError> for TheraminError {
+    fn from(e: midir::PortInfoError) -> Self {
+        TheraminError::MidiPortInfo(e.to_string())
+    }
+}
+
+impl<T> From<midir::ConnectError<T>> for TheraminError {
+    fn from(e: midir::ConnectError<T>) -> Self {
+        TheraminError::MidiConnect(e.to_string())
+    }
+}
+
+impl From<midir::SendError> for TheraminError {
+    fn from(e: midir::SendError) -> Self {
+        TheraminError::MidiSend(e.to_string())
+    }
+}
+
+impl<T> From<TrySendError<T>> for TheraminError {
+    fn from(e: TrySendError<T>) -> Self {
+        match e {
+            TrySendError::Full(_) => TheraminError::MsgChannelFull,
+            TrySendError::Closed(_) => TheraminError::MsgChannelClosed,
+        }
+    }
+}
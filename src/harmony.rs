@@ -0,0 +1,68 @@
+/// Notes a chord can add to the one played
+pub const CHORD_EXTRA_NOTES: usize = 3;
+pub const HARMONY_VOICES: usize = 2;
+/// Slots per period that stacking every other one makes thirds
+const DIATONIC_NOTES: usize = 7;
+/// Most notes a single played note can turn into
+pub const MAX_CHORD_NOTES: usize = 1 + CHORD_EXTRA_NOTES + HARMONY_VOICES;
+
+/// A note added to the one played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordTone {
+    /// Slots of the tuning up from the one played, so it follows the scale
+    Degrees(i8),
+    /// Same bend as the note played
+    Semitones(i8),
+}
+
+/// Triads and sevenths stack every other degree of a 7 note scale, with any other tuning
+/// they're major chords in semitones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChordMode {
+    #[default]
+    Off,
+    /// Fifth and octave
+    Power,
+    Triad,
+    Seventh,
+    /// Semitones from the note played
+    Fixed([Option<i8>; CHORD_EXTRA_NOTES]),
+}
+
+impl ChordMode {
+    /// `slots_per_period` is how many of the tuning's slots fall in each period
+    pub fn tones(self, slots_per_period: usize) -> Vec<ChordTone> {
+        let diatonic = slots_per_period == DIATONIC_NOTES;
+        match self {
+            ChordMode::Off => Vec::new(),
+            ChordMode::Power => vec![ChordTone::Semitones(7), ChordTone::Semitones(12)],
+            ChordMode::Triad if diatonic => vec![ChordTone::Degrees(2), ChordTone::Degrees(4)],
+            ChordMode::Triad => vec![ChordTone::Semitones(4), ChordTone::Semitones(7)],
+            ChordMode::Seventh if diatonic => vec![
+                ChordTone::Degrees(2),
+                ChordTone::Degrees(4),
+                ChordTone::Degrees(6),
+            ],
+            ChordMode::Seventh => vec![
+                ChordTone::Semitones(4),
+                ChordTone::Semitones(7),
+                ChordTone::Semitones(11),
+            ],
+            ChordMode::Fixed(intervals) => intervals
+                .into_iter()
+                .flatten()
+                .map(ChordTone::Semitones)
+                .collect(),
+        }
+    }
+}
+
+/// Parallel voices that move with the note played, in degrees of the tuning either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Harmony(pub [Option<i8>; HARMONY_VOICES]);
+
+impl Harmony {
+    pub fn tones(self) -> impl Iterator<Item = ChordTone> {
+        self.0.into_iter().flatten().map(ChordTone::Degrees)
+    }
+}
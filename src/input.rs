@@ -1,55 +1,772 @@
-use crate::midi::{Pitch, HIGHEST_MIDI_NOTE};
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+use crate::midi::{Expression, Pitch, HIGHEST_MIDI_NOTE};
+
+/// Mouse counts to move the Y axis from bottom to top
+const Y_TRAVEL: f32 = 1000.0;
+/// Cutoff for the speed estimate in the one euro filter, the value its paper suggests
+const ONE_EURO_SPEED_CUTOFF: f32 = 1.0;
+/// Longest a press on an absolute device waits for its position before it's played where
+/// the pointer last was
+const PRESS_WAIT: Duration = Duration::from_millis(20);
+pub const CURVE_POINTS: usize = 4;
+/// A gentle acceleration to start editing a custom curve from
+pub const DEFAULT_CURVE_POINTS: [(f32, f32); CURVE_POINTS] =
+    [(0.0, 0.0), (5.0, 5.0), (20.0, 40.0), (50.0, 200.0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MappingMode {
+    /// Movement nudges the position along, like a mouse
+    #[default]
+    Relative,
+    /// The device's reported range covers the whole bar, like a tablet
+    Absolute,
+}
+
+/// A slot's width as a percentage of the standard one
+pub const DEFAULT_WIDTH_PERCENT: u8 = 100;
+pub const MIN_WIDTH_PERCENT: u8 = 20;
+pub const MAX_WIDTH_PERCENT: u8 = 250;
+
+/// How wide each key's slot is, by key so widths stay put when the tuning or range changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteWidths(pub [u8; HIGHEST_MIDI_NOTE as usize + 1]);
+
+impl Default for NoteWidths {
+    fn default() -> Self {
+        NoteWidths([DEFAULT_WIDTH_PERCENT; HIGHEST_MIDI_NOTE as usize + 1])
+    }
+}
+
+impl NoteWidths {
+    pub fn get(&self, key: Pitch) -> u8 {
+        self.0[key as usize]
+    }
+
+    pub fn set(&mut self, key: Pitch, percent: u8) {
+        self.0[key as usize] = percent.clamp(MIN_WIDTH_PERCENT, MAX_WIDTH_PERCENT);
+    }
+}
+
+/// What relative movement does past either end of the bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeMode {
+    #[default]
+    Clamp,
+    /// Comes back in at the other end
+    Wrap,
+    /// Reflects off the end by however far it went past
+    Bounce,
+}
+
+/// Which of several merged devices gets heard when more than one is pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    #[default]
+    LastPressed,
+    HighestNote,
+    /// Every device gets its own voice
+    All,
+}
+
+/// Buttons that can be bound, ManyMouse numbers them from 0 with the left button first
+pub const BUTTONS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    /// Sounds the note while held
+    Play,
+    /// CC64 while held, or the handler's own pedal with `HoldMode::Pedal`
+    Sustain,
+    OctaveUp,
+    OctaveDown,
+    /// Each press turns the note on or off
+    Latch,
+    /// Stops every note on every device
+    Panic,
+    /// Jumps to the middle of the bar
+    Recenter,
+    /// Loads the device's next saved preset
+    NextPreset,
+}
+
+/// What each button does, `None` ignores it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonBindings(pub [Option<ButtonAction>; BUTTONS]);
+
+impl Default for ButtonBindings {
+    fn default() -> Self {
+        let mut bindings = [None; BUTTONS];
+        bindings[0] = Some(ButtonAction::Play);
+        ButtonBindings(bindings)
+    }
+}
+
+impl ButtonBindings {
+    pub fn get(&self, button: u32) -> Option<ButtonAction> {
+        self.0.get(button as usize).copied().flatten()
+    }
+}
+
+/// What letting go of the play button does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HoldMode {
+    /// The note stops
+    #[default]
+    Off,
+    /// Nothing, each press turns the note on or off
+    Latch,
+    /// The last note keeps sounding until the next press
+    HoldLast,
+    /// Notes keep sounding while a sustain button is down, done by the handler rather than
+    /// with CC64
+    Pedal,
+}
+
+/// What each tick of the scroll wheel does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrollAction {
+    #[default]
+    Off,
+    Octave,
+    /// A semitone a tick
+    Transpose,
+    Velocity,
+    /// Steps the CC's value up and down
+    Cc(u8),
+}
+
+/// Low pass filtering between the raw position and the one played
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Smoothing {
+    #[default]
+    Off,
+    /// Exponential moving average, catching up a fraction 1 - 1/e of the way each time constant
+    Ema { time_constant_ms: f32 },
+    /// Smooths hard when slow and barely at all when fast, see
+    /// https://gery.casiez.net/1euro/. Cutoffs are in Hz and speed in slots per second
+    OneEuro { min_cutoff: f32, beta: f32 },
+}
+
+/// Multiplies relative movement after the response curve, above 1 the bar goes by faster
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sensitivity(pub f32);
+
+impl Sensitivity {
+    pub const MIN: f32 = 0.25;
+    pub const MAX: f32 = 4.0;
+
+    /// Even steps in ratio across the range, so the middle of a control is 1
+    pub fn from_7bit(value: u8) -> Self {
+        let frac = value.min(0x7f) as f32 / 0x7f as f32;
+        Sensitivity(Self::MIN * (Self::MAX / Self::MIN).powf(frac))
+    }
+}
+
+impl Default for Sensitivity {
+    fn default() -> Self {
+        Sensitivity(1.0)
+    }
+}
+
+/// Maps the counts of each relative movement to how far the position moves
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ResponseCurve {
+    #[default]
+    Linear,
+    /// Counts to the power of the exponent, above 1 small moves get finer and big ones further
+    Exponential(f32),
+    /// Straight lines between (counts, distance) points sorted by counts, carrying on past the
+    /// last point at the last line's slope
+    Custom([(f32, f32); CURVE_POINTS]),
+}
+
+impl ResponseCurve {
+    pub fn apply(&self, mov: f32) -> f32 {
+        let counts = mov.abs();
+        let distance = match self {
+            ResponseCurve::Linear => counts,
+            ResponseCurve::Exponential(exponent) => counts.powf(*exponent),
+            ResponseCurve::Custom(points) => {
+                let segment = points
+                    .windows(2)
+                    .find(|pair| counts <= pair[1].0)
+                    .unwrap_or(&points[CURVE_POINTS - 2..]);
+                let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+                if x1 <= x0 {
+                    y1
+                } else {
+                    y0 + (counts - x0) * (y1 - y0) / (x1 - x0)
+                }
+            }
+        };
+        distance.max(0.0).copysign(mov)
+    }
+}
+
+struct Smoother {
+    smoothing: Smoothing,
+    value: f32,
+    /// Smoothed rate of change of the input, for the one euro filter
+    speed: f32,
+    prev_input: f32,
+    last_step: Option<Instant>,
+}
+
+impl Smoother {
+    fn new(smoothing: Smoothing, value: f32) -> Self {
+        Smoother {
+            smoothing,
+            value,
+            speed: 0.0,
+            prev_input: value,
+            last_step: None,
+        }
+    }
+
+    /// Jumps straight to `value`
+    fn reset(&mut self, value: f32) {
+        *self = Smoother::new(self.smoothing, value);
+    }
+
+    /// Moves towards `input` by however much time passed since the last step
+    fn step(&mut self, input: f32) -> f32 {
+        self.step_at(input, Instant::now())
+    }
+
+    fn step_at(&mut self, input: f32, now: Instant) -> f32 {
+        let dt = self
+            .last_step
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self.last_step = Some(now);
+        let alpha = match self.smoothing {
+            Smoothing::Off => 1.0,
+            Smoothing::Ema { time_constant_ms } if time_constant_ms > 0.0 => {
+                1.0 - (-dt * 1000.0 / time_constant_ms).exp()
+            }
+            Smoothing::Ema { .. } => 1.0,
+            Smoothing::OneEuro { min_cutoff, beta } => {
+                if dt > 0.0 {
+                    let speed = (input - self.prev_input) / dt;
+                    self.speed += low_pass_alpha(ONE_EURO_SPEED_CUTOFF, dt) * (speed - self.speed);
+                }
+                low_pass_alpha(min_cutoff + beta * self.speed.abs(), dt)
+            }
+        };
+        self.prev_input = input;
+        self.value += alpha * (input - self.value);
+        self.value
+    }
+}
+
+/// Smoothing factor of a first order low pass at `cutoff` Hz sampled `dt` seconds apart
+fn low_pass_alpha(cutoff: f32, dt: f32) -> f32 {
+    if cutoff <= 0.0 {
+        return 0.0;
+    }
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+/// Where on the bar a pointer is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarPos {
+    /// The slot being played, which lags behind `pos` by the hysteresis band
+    pub slot: usize,
+    /// In slots, 0.5 is the middle of the first
+    pub pos: f32,
+}
 
 pub struct InputHandler {
+    /// Where the device is pointing, `pos` follows it through `smoother`
+    target: f32,
+    smoother: Smoother,
+    pub curve: ResponseCurve,
+    pub sensitivity: Sensitivity,
+    pub edge: EdgeMode,
     pos: u32,
+    /// Only changes once `pos` is past the slot's edge by the hysteresis band
+    slot: usize,
     max_pos: u32,
+    note_width: u16,
+    /// One per slot of the tuning, see `tuning::Tuning::slots`
     note_boundaries: Vec<u32>,
     pub playing: bool,
+    hold: HoldMode,
+    /// Where the last note was let go of, while it's kept sounding by `HoldMode::HoldLast`
+    held: Option<BarPos>,
+    pub mapping: MappingMode,
+    /// How far into a neighbouring slot `pos` must go to move to it, as a fraction of a
+    /// slot's width up to a half
+    pub hysteresis: f32,
+    pending_presses: Vec<bool>,
+    /// An absolute position arrived since presses were last taken
+    positioned: bool,
+    /// When `take_placed_presses` started holding a press back for its position
+    press_waiting_since: Option<Instant>,
+    /// 0 at the bottom to 1 at the top
+    y: f32,
+    pressure: f32,
 }
 
 impl InputHandler {
-    pub fn new(note_width: u16) -> Self {
-        let note_boundaries = (0..(HIGHEST_MIDI_NOTE + 1))
-            .map(|i| (i + 1) as u32 * note_width as u32)
-            .collect();
-        let max_pos = (HIGHEST_MIDI_NOTE + 1) as u32 * note_width as u32;
-        InputHandler {
-            pos: max_pos / 2,
-            max_pos,
-            note_boundaries,
+    /// `slot_widths` are percentages of `note_width`, see `set_slot_widths`
+    pub fn new(note_width: u16, slot_widths: &[u8], mapping: MappingMode) -> Self {
+        let mut input_h = InputHandler {
+            target: 0.0,
+            smoother: Smoother::new(Smoothing::Off, 0.0),
+            curve: ResponseCurve::Linear,
+            sensitivity: Sensitivity::default(),
+            edge: EdgeMode::Clamp,
+            pos: 0,
+            slot: 0,
+            max_pos: 0,
+            note_width,
+            note_boundaries: Vec::new(),
             playing: false,
+            hold: HoldMode::Off,
+            held: None,
+            mapping,
+            hysteresis: 0.0,
+            pending_presses: Vec::new(),
+            positioned: false,
+            press_waiting_since: None,
+            y: 0.5,
+            pressure: 1.0,
+        };
+        input_h.set_slot_widths(slot_widths);
+        input_h
+    }
+
+    /// Lays the bar out again with a slot per width, each a percentage of `note_width`.
+    /// The position stays the same fraction of the way along
+    pub fn set_slot_widths(&mut self, slot_widths: &[u8]) {
+        let frac = match self.max_pos {
+            0 => 0.5,
+            max_pos => self.pos as f64 / max_pos as f64,
+        };
+        let mut edge = 0;
+        self.note_boundaries = slot_widths
+            .iter()
+            .map(|percent| {
+                edge += (self.note_width as u32 * *percent as u32 / 100).max(1);
+                edge
+            })
+            .collect();
+        if self.note_boundaries.is_empty() {
+            self.note_boundaries.push(self.note_width as u32);
+        }
+        self.max_pos = *self.note_boundaries.last().unwrap();
+        self.jump_to((frac * self.max_pos as f64) as f32);
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        if smoothing != self.smoother.smoothing {
+            self.smoother.smoothing = smoothing;
+            self.smoother
+                .reset(self.pos as f32 / self.note_width as f32);
         }
     }
 
     pub fn reset(&mut self) {
-        self.pos = self.max_pos / 2;
+        self.recenter();
+        self.let_go();
+        self.y = 0.5;
+        self.pressure = 1.0;
+    }
+
+    /// Stops playing and forgets any held note or pending press
+    pub fn let_go(&mut self) {
         self.playing = false;
+        self.held = None;
+        self.pending_presses.clear();
+        self.press_waiting_since = None;
     }
 
-    pub fn float_pos(&self) -> f32 {
-        (HIGHEST_MIDI_NOTE + 1) as f32 * self.pos as f32 / self.max_pos as f32
+    pub fn set_hold(&mut self, hold: HoldMode) {
+        if hold != self.hold {
+            self.held = None;
+        }
+        self.hold = hold;
+    }
+
+    /// Touchpads report a touch before its position, so presses wait for the end of the frame
+    /// to be applied with `take_pending_presses`
+    pub fn queue_press(&mut self, pressed: bool) {
+        match self.hold {
+            HoldMode::Latch if pressed => self.queue_toggle(),
+            HoldMode::Latch => (),
+            _ => self.pending_presses.push(pressed),
+        }
+    }
+
+    /// The opposite of whatever the last press left it as
+    pub fn queue_toggle(&mut self) {
+        let playing = self.pending_presses.last().copied().unwrap_or(self.playing);
+        self.pending_presses.push(!playing);
     }
 
-    pub fn handle_rel_move(&mut self, mov: i32) -> Pitch {
-        if mov > 0 {
-            self.pos = (self.pos + mov as u32).min(self.max_pos - 1);
+    pub fn take_pending_presses(&mut self) -> Vec<bool> {
+        std::mem::take(&mut self.pending_presses)
+    }
+
+    /// For devices with no frames of their own, where a touch and its position can come in
+    /// different batches. With absolute mapping a press waits for the next position, up to
+    /// `PRESS_WAIT`, so it isn't played where the last touch was
+    pub fn take_placed_presses(&mut self, now: Instant) -> Vec<bool> {
+        let waits = self.mapping == MappingMode::Absolute
+            && !self.positioned
+            && self.pending_presses.first() == Some(&true);
+        if waits {
+            let since = *self.press_waiting_since.get_or_insert(now);
+            if now.duration_since(since) < PRESS_WAIT {
+                return Vec::new();
+            }
+        }
+        self.press_waiting_since = None;
+        self.positioned = false;
+        self.take_pending_presses()
+    }
+
+    /// Applies a press from `take_pending_presses`
+    pub fn set_playing(&mut self, playing: bool) {
+        self.held = match (self.hold, playing) {
+            (HoldMode::HoldLast, false) if self.playing => Some(self.bar_pos()),
+            (_, false) => self.held,
+            (_, true) => None,
+        };
+        self.playing = playing;
+    }
+
+    /// Where the note should be if there is one, held notes stay where they were let go
+    pub fn sounding(&self) -> Option<BarPos> {
+        if self.playing {
+            Some(self.bar_pos())
         } else {
-            let mov = mov.abs() as u32;
-            if mov > self.pos {
-                self.pos = 0;
-            } else {
-                self.pos = self.pos - mov;
+            self.held
+        }
+    }
+
+    /// In slots, however wide each one is
+    pub fn float_pos(&self) -> f32 {
+        let slot = self.slot_under_pos();
+        let (lower, upper) = self.slot_edges(slot);
+        slot as f32 + (self.pos - lower) as f32 / (upper - lower) as f32
+    }
+
+    /// `mov` goes through `curve` then `sensitivity` first
+    pub fn handle_rel_move(&mut self, mov: i32) {
+        let end = (self.max_pos - 1) as f32;
+        let sensitivity = self.sensitivity.0.clamp(Sensitivity::MIN, Sensitivity::MAX);
+        let target = self.target + self.curve.apply(mov as f32) * sensitivity;
+        match self.edge {
+            EdgeMode::Clamp => self.target = target.clamp(0.0, end),
+            EdgeMode::Wrap if !(0.0..=end).contains(&target) => {
+                // smoothing across the whole bar would sweep every note in between
+                self.jump_to(target.rem_euclid(self.max_pos as f32));
+                return;
             }
+            EdgeMode::Wrap => self.target = target,
+            EdgeMode::Bounce => {
+                // folds back and forth so even a huge move lands on the bar
+                let folded = target.rem_euclid((2.0 * end).max(1.0));
+                self.target = if folded > end {
+                    (2.0 * end - folded).max(0.0)
+                } else {
+                    folded
+                };
+            }
+        }
+        self.tick();
+    }
+
+    /// Maps `value` from the device's `minval..=maxval` onto the bar
+    pub fn handle_abs_move(&mut self, value: i32, minval: i32, maxval: i32) {
+        if let Some(frac) = normalise(value, minval, maxval) {
+            self.target = (frac * self.max_pos as f32).min((self.max_pos - 1) as f32);
+            self.positioned = true;
+        }
+        self.tick();
+    }
+
+    /// Lets the smoothed position catch up with the device, call this regularly as it keeps
+    /// moving after the device stops. Returns whether the position changed
+    pub fn tick(&mut self) -> bool {
+        let width = self.note_width as f32;
+        let smoothed = self.smoother.step(self.target / width) * width;
+        let pos = (smoothed.round() as u32).min(self.max_pos - 1);
+        let moved = pos != self.pos;
+        self.pos = pos;
+        self.update_slot();
+        moved
+    }
+
+    pub fn recenter(&mut self) {
+        self.jump_to(self.max_pos as f32 / 2.0);
+    }
+
+    fn jump_to(&mut self, pos: f32) {
+        self.target = pos.clamp(0.0, (self.max_pos - 1) as f32);
+        self.pos = self.target as u32;
+        self.smoother.reset(self.target / self.note_width as f32);
+        self.slot = self.slot_under_pos();
+    }
+
+    pub fn handle_rel_y(&mut self, mov: i32) {
+        // screen coordinates, so moving up is negative
+        self.y = (self.y - mov as f32 / Y_TRAVEL).clamp(0.0, 1.0);
+    }
+
+    pub fn handle_abs_y(&mut self, value: i32, minval: i32, maxval: i32) {
+        if let Some(frac) = normalise(value, minval, maxval) {
+            self.y = 1.0 - frac;
+        }
+    }
+
+    pub fn handle_pressure(&mut self, value: i32, minval: i32, maxval: i32) {
+        if let Some(frac) = normalise(value, minval, maxval) {
+            self.pressure = frac;
+        }
+    }
+
+    /// The bend is left at 0, it depends on the tuning so `Tuning::realise` fills it in
+    pub fn expression(&self) -> Expression {
+        Expression {
+            bend: 0.0,
+            timbre: self.y,
+            pressure: self.pressure,
+        }
+    }
+
+    pub fn bar_pos(&self) -> BarPos {
+        BarPos {
+            slot: self.slot,
+            pos: self.float_pos(),
+        }
+    }
+
+    /// Holds on to the current slot until `pos` is clear of its edges
+    fn update_slot(&mut self) {
+        let under = self.slot_under_pos();
+        if under == self.slot {
+            return;
         }
-        self.pitch_from_pos()
+        let (lower, upper) = self.slot_edges(self.slot);
+        let band = ((upper - lower) as f32 * self.hysteresis.clamp(0.0, 0.5)) as u32;
+        if self.pos + band < lower || self.pos >= upper + band {
+            self.slot = under;
+        }
+    }
+
+    fn slot_edges(&self, slot: usize) -> (u32, u32) {
+        let lower = match slot {
+            0 => 0,
+            slot => self.note_boundaries[slot - 1],
+        };
+        (lower, self.note_boundaries[slot])
     }
 
-    pub fn pitch_from_pos(&self) -> Pitch {
+    fn slot_under_pos(&self) -> usize {
         for (i, bound) in self.note_boundaries.iter().enumerate() {
             if self.pos < *bound {
-                return i as Pitch;
+                return i;
             }
         }
         unreachable!("damn, my bad")
     }
 }
+
+/// Where `value` sits in `minval..=maxval` from 0 to 1, `None` if the range is empty
+fn normalise(value: i32, minval: i32, maxval: i32) -> Option<f32> {
+    if maxval <= minval {
+        return None;
+    }
+    let value = value.clamp(minval, maxval);
+    Some(((value as i64 - minval as i64) as f64 / (maxval as i64 - minval as i64) as f64) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Five slots 100 counts wide, starting in the middle of the third
+    fn five_slots() -> InputHandler {
+        InputHandler::new(100, &[100; 5], MappingMode::Relative)
+    }
+
+    #[test]
+    fn slot_changes_at_the_edge_without_hysteresis() {
+        let mut input_h = five_slots();
+        assert_eq!((input_h.pos, input_h.slot), (250, 2));
+        input_h.handle_rel_move(49);
+        assert_eq!(input_h.slot, 2);
+        input_h.handle_rel_move(1);
+        assert_eq!(input_h.slot, 3);
+    }
+
+    #[test]
+    fn slot_is_held_through_the_hysteresis_band() {
+        let mut input_h = five_slots();
+        input_h.hysteresis = 0.25;
+        input_h.handle_rel_move(74);
+        assert_eq!((input_h.pos, input_h.slot), (324, 2));
+        input_h.handle_rel_move(1);
+        assert_eq!(input_h.slot, 3);
+        // coming back the band is on the other side of the edge
+        input_h.handle_rel_move(-50);
+        assert_eq!((input_h.pos, input_h.slot), (275, 3));
+        input_h.handle_rel_move(-1);
+        assert_eq!(input_h.slot, 2);
+    }
+
+    #[test]
+    fn curves_keep_the_direction() {
+        assert_eq!(ResponseCurve::Linear.apply(-3.0), -3.0);
+        assert_eq!(ResponseCurve::Exponential(2.0).apply(3.0), 9.0);
+        assert_eq!(ResponseCurve::Exponential(2.0).apply(-3.0), -9.0);
+        assert_eq!(ResponseCurve::Exponential(2.0).apply(0.0), 0.0);
+    }
+
+    #[test]
+    fn custom_curve_interpolates_and_carries_on() {
+        let curve = ResponseCurve::Custom(DEFAULT_CURVE_POINTS);
+        assert_eq!(curve.apply(5.0), 5.0);
+        assert_eq!(curve.apply(12.5), 22.5);
+        assert_eq!(curve.apply(-20.0), -40.0);
+        // past the last point at the last line's slope
+        assert!((curve.apply(60.0) - (200.0 + 10.0 * 160.0 / 30.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ema_catches_up_a_time_constant_at_a_time() {
+        let start = Instant::now();
+        let mut smoother = Smoother::new(
+            Smoothing::Ema {
+                time_constant_ms: 100.0,
+            },
+            0.0,
+        );
+        assert_eq!(smoother.step_at(1.0, start), 0.0);
+        let value = smoother.step_at(1.0, start + Duration::from_millis(100));
+        assert!((value - (1.0 - (-1f32).exp())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn one_euro_follows_fast_moves_closer() {
+        let start = Instant::now();
+        let later = start + Duration::from_millis(10);
+        let mut slow = Smoother::new(
+            Smoothing::OneEuro {
+                min_cutoff: 1.0,
+                beta: 0.0,
+            },
+            0.0,
+        );
+        let mut fast = Smoother::new(
+            Smoothing::OneEuro {
+                min_cutoff: 1.0,
+                beta: 1.0,
+            },
+            0.0,
+        );
+        for smoother in [&mut slow, &mut fast] {
+            smoother.step_at(0.0, start);
+        }
+        let (slow, fast) = (slow.step_at(1.0, later), fast.step_at(1.0, later));
+        assert!(0.0 < slow && slow < fast && fast < 1.0);
+    }
+
+    #[test]
+    fn smoothing_off_jumps_straight_there() {
+        let mut smoother = Smoother::new(Smoothing::Off, 0.0);
+        assert_eq!(smoother.step_at(3.0, Instant::now()), 3.0);
+    }
+
+    #[test]
+    fn hysteresis_band_is_at_most_half_a_slot() {
+        let mut input_h = five_slots();
+        input_h.hysteresis = 2.0;
+        input_h.handle_rel_move(100);
+        assert_eq!((input_h.pos, input_h.slot), (350, 3));
+    }
+
+    #[test]
+    fn sensitivity_scales_movement() {
+        let mut input_h = five_slots();
+        input_h.sensitivity = Sensitivity(2.0);
+        input_h.handle_rel_move(20);
+        assert_eq!(input_h.pos, 290);
+        assert_eq!(Sensitivity::from_7bit(0), Sensitivity(Sensitivity::MIN));
+        assert!((Sensitivity::from_7bit(0x7f).0 - Sensitivity::MAX).abs() < 1e-5);
+    }
+
+    #[test]
+    fn absolute_press_waits_for_its_position() {
+        let start = Instant::now();
+        let mut input_h = InputHandler::new(100, &[100; 5], MappingMode::Absolute);
+        input_h.queue_press(true);
+        assert!(input_h.take_placed_presses(start).is_empty());
+        input_h.handle_abs_move(450, 0, 500);
+        assert_eq!(input_h.take_placed_presses(start), [true]);
+        // a lift doesn't need a position
+        input_h.queue_press(false);
+        assert_eq!(input_h.take_placed_presses(start), [false]);
+    }
+
+    #[test]
+    fn absolute_press_without_a_position_plays_after_a_wait() {
+        let start = Instant::now();
+        let mut input_h = InputHandler::new(100, &[100; 5], MappingMode::Absolute);
+        input_h.queue_press(true);
+        assert!(input_h.take_placed_presses(start).is_empty());
+        assert_eq!(input_h.take_placed_presses(start + PRESS_WAIT), [true]);
+    }
+
+    #[test]
+    fn relative_press_plays_straight_away() {
+        let mut input_h = five_slots();
+        input_h.queue_press(true);
+        assert_eq!(input_h.take_placed_presses(Instant::now()), [true]);
+    }
+
+    #[test]
+    fn clamp_stops_at_both_ends() {
+        let mut input_h = five_slots();
+        input_h.handle_rel_move(1000);
+        assert_eq!((input_h.pos, input_h.slot), (499, 4));
+        input_h.handle_rel_move(-1000);
+        assert_eq!((input_h.pos, input_h.slot), (0, 0));
+    }
+
+    #[test]
+    fn wrap_comes_in_at_the_other_end() {
+        let mut input_h = five_slots();
+        input_h.edge = EdgeMode::Wrap;
+        input_h.handle_rel_move(249);
+        assert_eq!(input_h.pos, 499);
+        input_h.handle_rel_move(1);
+        assert_eq!((input_h.pos, input_h.slot), (0, 0));
+        input_h.handle_rel_move(-1);
+        assert_eq!((input_h.pos, input_h.slot), (499, 4));
+        input_h.handle_rel_move(-550);
+        assert_eq!(input_h.pos, 449);
+    }
+
+    #[test]
+    fn bounce_reflects_off_the_ends() {
+        let mut input_h = five_slots();
+        input_h.edge = EdgeMode::Bounce;
+        input_h.handle_rel_move(249);
+        assert_eq!(input_h.pos, 499);
+        input_h.handle_rel_move(10);
+        assert_eq!(input_h.pos, 489);
+        input_h.handle_rel_move(-500);
+        assert_eq!(input_h.pos, 11);
+        // far enough to fold back and forth more than once
+        input_h.handle_rel_move(2000);
+        assert_eq!(input_h.pos, 15);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use midir::{os::unix::VirtualInput, MidiInput, MidiInputConnection};
+
+use crate::{
+    error::Result,
+    midi::{Pitch, HIGHEST_MIDI_NOTE, NOTE_OFF_MSG, NOTE_ON_MSG},
+    tuning::{KeyboardMap, Scale, Tuning},
+};
+
+/// How a theremin's tuning follows the keys held on the keyboard port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FollowKeys {
+    #[default]
+    Off,
+    /// The lowest key moves degree 0 of the theremin's own scale
+    Root,
+    /// Only the keys held are on the bar, in 12 tone from the lowest
+    Chord,
+}
+
+impl FollowKeys {
+    /// `None` when not following or nothing's been held yet
+    pub fn tuning(
+        self,
+        keys: &[Pitch],
+        scale: Option<&Scale>,
+        kbm: Option<&KeyboardMap>,
+    ) -> Result<Option<Tuning>> {
+        let Some(&lowest) = keys.iter().min() else {
+            return Ok(None);
+        };
+        let tuning = match self {
+            FollowKeys::Off => return Ok(None),
+            FollowKeys::Root => Tuning::new(
+                &scale.cloned().unwrap_or_default(),
+                &kbm.cloned().unwrap_or_default().rooted_on(lowest),
+            )?,
+            FollowKeys::Chord => Tuning::new(
+                &Scale::from_keys(keys),
+                &KeyboardMap::default().rooted_on(lowest),
+            )?,
+        };
+        Ok(Some(tuning))
+    }
+}
+
+/// Keeps track of the keys held down on a virtual input port
+pub struct KeysIn {
+    /// A bit per key, 0 to 63 then 64 to 127
+    held: Arc<[AtomicU64; 2]>,
+    last_held: Vec<Pitch>,
+    _conn_in: MidiInputConnection<()>,
+}
+
+impl KeysIn {
+    pub fn open(name: &str) -> Result<Self> {
+        let held = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+        let keys = held.clone();
+        let conn_in = MidiInput::new("Theramin midi in")?.create_virtual(
+            name,
+            move |_, msg, _| {
+                let &[status, key, vel, ..] = msg else {
+                    return;
+                };
+                let (half, bit) = (&keys[(key >> 6) as usize & 1], 1 << (key & 63));
+                match status & 0xF0 {
+                    NOTE_ON_MSG if vel > 0 => half.fetch_or(bit, Ordering::AcqRel),
+                    // note on at 0 velocity is a note off
+                    NOTE_ON_MSG | NOTE_OFF_MSG => half.fetch_and(!bit, Ordering::AcqRel),
+                    _ => return,
+                };
+            },
+            (),
+        )?;
+        Ok(KeysIn {
+            held,
+            last_held: Vec::new(),
+            _conn_in: conn_in,
+        })
+    }
+
+    /// The keys held, low to high, if they've changed. Letting go of them all doesn't count,
+    /// so the theremins carry on in the last chord rather than having nothing to play
+    pub fn take_change(&mut self) -> Option<Vec<Pitch>> {
+        let halves = [0, 1].map(|half| self.held[half].load(Ordering::Acquire));
+        let held: Vec<Pitch> = (0..=HIGHEST_MIDI_NOTE)
+            .filter(|key| halves[(key >> 6) as usize] & 1 << (key & 63) != 0)
+            .collect();
+        if held.is_empty() || held == self.last_held {
+            return None;
+        }
+        self.last_held = held.clone();
+        Some(held)
+    }
+
+    /// The keys last held, for a theremin that's just started following them
+    pub fn last_held(&self) -> &[Pitch] {
+        &self.last_held
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::mpsc;
+
+use midir::{os::unix::VirtualInput, MidiInput, MidiInputConnection};
+
+use crate::{
+    error::Result,
+    input::ButtonAction,
+    midi::{CC_MSG, NOTE_OFF_MSG, NOTE_ON_MSG},
+};
+
+/// CC values from here up count as a switch being down
+const CC_DOWN: u8 = 64;
+
+/// A control on the control port, from any channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnSource {
+    Cc(u8),
+    Note(u8),
+}
+
+/// What a learned control changes on its theremin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnTarget {
+    /// Held while the note or CC is down
+    Button(ButtonAction),
+    ToggleLegato,
+    ToggleArp,
+    /// Off, power, triad, seventh, the last fixed intervals if there were any then back round
+    NextChord,
+    /// Off, root, chord then back round
+    NextFollowKeys,
+    /// Through the scales loaded on the device, oldest first
+    NextScale,
+    /// The rest follow the CC's value or the note's velocity
+    Hysteresis,
+    Sensitivity,
+    LowestKey,
+    HighestKey,
+    YControllerCc,
+    /// 0 turns portamento off
+    Glide,
+    Velocity,
+}
+
+impl LearnTarget {
+    /// Whether it takes the control's value rather than just whether it's down
+    pub fn is_continuous(self) -> bool {
+        matches!(
+            self,
+            LearnTarget::Hysteresis
+                | LearnTarget::Sensitivity
+                | LearnTarget::LowestKey
+                | LearnTarget::HighestKey
+                | LearnTarget::YControllerCc
+                | LearnTarget::Glide
+                | LearnTarget::Velocity
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlEvent {
+    pub source: LearnSource,
+    /// The CC's value, the velocity of a note on or 0 for a note off
+    pub value: u8,
+}
+
+impl ControlEvent {
+    pub fn down(self) -> bool {
+        match self.source {
+            LearnSource::Cc(_) => self.value >= CC_DOWN,
+            LearnSource::Note(_) => self.value > 0,
+        }
+    }
+}
+
+/// Notes and CCs sent to a virtual input port, queued for the worker to read
+pub struct ControlIn {
+    events: mpsc::Receiver<ControlEvent>,
+    _conn_in: MidiInputConnection<()>,
+}
+
+impl ControlIn {
+    pub fn open(name: &str) -> Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let conn_in = MidiInput::new("Theramin midi in")?.create_virtual(
+            name,
+            move |_, msg, _| {
+                let &[status, num, value, ..] = msg else {
+                    return;
+                };
+                let (source, value) = match status & 0xF0 {
+                    CC_MSG => (LearnSource::Cc(num), value),
+                    // note on at 0 velocity is a note off
+                    NOTE_ON_MSG => (LearnSource::Note(num), value),
+                    NOTE_OFF_MSG => (LearnSource::Note(num), 0),
+                    _ => return,
+                };
+                // the worker's gone if this fails
+                let _ = tx.send(ControlEvent { source, value });
+            },
+            (),
+        )?;
+        Ok(ControlIn {
+            events,
+            _conn_in: conn_in,
+        })
+    }
+
+    pub fn poll(&self) -> impl Iterator<Item = ControlEvent> + '_ {
+        self.events.try_iter()
+    }
+}
@@ -1,5 +1,16 @@
+pub mod arpeggiator;
+pub mod clock;
+pub mod error;
+pub mod harmony;
 pub mod input;
+pub mod keys;
+pub mod learn;
 pub mod manymouse;
 pub mod midi;
+#[cfg(target_os = "linux")]
+pub mod multitouch;
+pub mod settings;
+pub mod tuning;
+pub mod ump;
 pub mod use_theramin_routine;
 pub use use_theramin_routine::*;
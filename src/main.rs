@@ -5,7 +5,100 @@ use dioxus_desktop::{
     tao::keyboard::KeyCode, use_window, use_wry_event_handler, Config, LogicalSize, WindowBuilder,
 };
 
-use theramin::{midi::HIGHEST_MIDI_NOTE, use_theramin_routine::*};
+use theramin::{
+    arpeggiator::{Arp, ArpPattern, ArpTempo, DEFAULT_BPM},
+    clock::Grid,
+    error::TheraminError,
+    harmony::{ChordMode, CHORD_EXTRA_NOTES},
+    input::{
+        ButtonAction, EdgeMode, HoldMode, MappingMode, MergePolicy, NoteWidths, ResponseCurve,
+        ScrollAction, Sensitivity, Smoothing, DEFAULT_CURVE_POINTS, MAX_WIDTH_PERCENT,
+        MIN_WIDTH_PERCENT,
+    },
+    keys::FollowKeys,
+    learn::{LearnSource, LearnTarget},
+    midi::{Controller, Pitch, VoiceRouting, HIGHEST_MIDI_NOTE},
+    settings::DevSettings,
+    tuning::{NoteRange, Slot, TuningOutput},
+    ump::ump_file_path,
+    use_theramin_routine::*,
+};
+
+/// CC5 value portamento starts at when turned on
+const DEFAULT_GLIDE_TIME: u8 = 20;
+const DEFAULT_EMA: Smoothing = Smoothing::Ema {
+    time_constant_ms: 30.0,
+};
+const DEFAULT_ONE_EURO: Smoothing = Smoothing::OneEuro {
+    min_cutoff: 1.0,
+    beta: 0.5,
+};
+const DEFAULT_EXPONENT: f32 = 1.5;
+/// Value in the select, what it says and the action it binds
+const BUTTON_ACTIONS: [(&str, &str, Option<ButtonAction>); 9] = [
+    ("none", "Nothing", None),
+    ("play", "Play", Some(ButtonAction::Play)),
+    ("sustain", "Sustain", Some(ButtonAction::Sustain)),
+    ("octave_up", "Octave up", Some(ButtonAction::OctaveUp)),
+    ("octave_down", "Octave down", Some(ButtonAction::OctaveDown)),
+    ("latch", "Latch", Some(ButtonAction::Latch)),
+    ("panic", "Panic", Some(ButtonAction::Panic)),
+    ("recenter", "Recenter", Some(ButtonAction::Recenter)),
+    ("next_preset", "Next preset", Some(ButtonAction::NextPreset)),
+];
+/// Value in the select, what it says and what a learned control changes
+const LEARN_TARGETS: [(&str, &str, LearnTarget); 20] = [
+    ("play", "Play", LearnTarget::Button(ButtonAction::Play)),
+    (
+        "sustain",
+        "Sustain",
+        LearnTarget::Button(ButtonAction::Sustain),
+    ),
+    (
+        "octave_up",
+        "Octave up",
+        LearnTarget::Button(ButtonAction::OctaveUp),
+    ),
+    (
+        "octave_down",
+        "Octave down",
+        LearnTarget::Button(ButtonAction::OctaveDown),
+    ),
+    ("latch", "Latch", LearnTarget::Button(ButtonAction::Latch)),
+    ("panic", "Panic", LearnTarget::Button(ButtonAction::Panic)),
+    (
+        "recenter",
+        "Recenter",
+        LearnTarget::Button(ButtonAction::Recenter),
+    ),
+    (
+        "next_preset",
+        "Next preset",
+        LearnTarget::Button(ButtonAction::NextPreset),
+    ),
+    ("legato", "Legato on/off", LearnTarget::ToggleLegato),
+    ("arp", "Arpeggiator on/off", LearnTarget::ToggleArp),
+    ("chord", "Next chord", LearnTarget::NextChord),
+    (
+        "follow_keys",
+        "Next keyboard follow mode",
+        LearnTarget::NextFollowKeys,
+    ),
+    ("scale", "Next loaded scale", LearnTarget::NextScale),
+    ("hysteresis", "Hysteresis", LearnTarget::Hysteresis),
+    ("sensitivity", "Sensitivity", LearnTarget::Sensitivity),
+    ("lowest_key", "Lowest key", LearnTarget::LowestKey),
+    ("highest_key", "Highest key", LearnTarget::HighestKey),
+    ("y_cc", "Y axis CC", LearnTarget::YControllerCc),
+    ("glide", "Glide time", LearnTarget::Glide),
+    ("velocity", "Velocity", LearnTarget::Velocity),
+];
+/// A major triad to start editing fixed intervals from
+const DEFAULT_FIXED_CHORD: [Option<i8>; CHORD_EXTRA_NOTES] = [Some(4), Some(7), None];
+/// Mod wheel, what a scroll wheel is most like
+const DEFAULT_SCROLL_CC: u8 = 1;
+/// Channel volume, CC 11 expression is the other usual choice
+const DEFAULT_VOLUME_CC: u8 = 7;
 
 fn main() {
     dioxus_desktop::launch::launch(
@@ -54,7 +147,41 @@ fn App() -> Element {
                 MouseHider {}
             },
             DevBar {},
-            ThereminList {},
+            div {
+                flex: "1 1 100%",
+                min_width: "0",
+                display: "flex",
+                flex_direction: "column",
+                ErrorBanner {},
+                ThereminList {},
+            }
+        }
+    }
+}
+
+#[component]
+fn ErrorBanner() -> Element {
+    let mut errors = use_theramin_errors();
+    if errors.read().is_empty() {
+        return None;
+    }
+    rsx! {
+        div {
+            border: "solid red",
+            background_color: "#4d0000",
+            for (err, times) in errors.read().iter() {
+                div {
+                    "{err}",
+                    if *times > 1 {
+                        " (x{times})"
+                    }
+                }
+            },
+            button {
+                "type": "button",
+                onclick: move |_| errors.write().clear(),
+                "Dismiss"
+            }
         }
     }
 }
@@ -62,10 +189,15 @@ fn App() -> Element {
 #[component]
 fn MouseHider() -> Element {
     let mut cursor_state: Signal<CursorState> = use_context();
+    let theramin_msg_tx: Signal<TheraminMsgTx> = use_context();
     let window = use_window();
     use_effect(move || {
         window.set_cursor_visible(false);
-        window.set_cursor_grab(true).unwrap();
+        if let Err(e) = window.set_cursor_grab(true) {
+            theramin_msg_tx
+                .read()
+                .report(TheraminError::CursorGrab(e.to_string()));
+        }
     });
     use dioxus_desktop::tao::event::Event::WindowEvent;
     use_wry_event_handler(move |ev, _| {
@@ -81,7 +213,11 @@ fn MouseHider() -> Element {
     let window = use_window();
     if *cursor_state.read() == CursorState::PendingUnGrab {
         window.set_cursor_visible(true);
-        window.set_cursor_grab(false).unwrap();
+        if let Err(e) = window.set_cursor_grab(false) {
+            theramin_msg_tx
+                .read()
+                .report(TheraminError::CursorGrab(e.to_string()));
+        }
         *cursor_state.write() = CursorState::NoGrab;
     }
     rsx! {
@@ -96,11 +232,21 @@ fn MouseHider() -> Element {
 
 #[component]
 fn DevBar() -> Element {
+    let driver_name = use_driver_name();
+    let driver_text = match driver_name.read().as_deref() {
+        Some(name) => format!("Driver: {}", name),
+        None => "No mouse driver".to_string(),
+    };
     rsx! {
         div {
             flex: "0 0 12em",
             border: "solid white",
             RefreshButton {},
+            div {
+                text_align: "center",
+                font_size: "small",
+                "{driver_text}"
+            },
             DevList {},
         }
     }
@@ -130,6 +276,8 @@ fn DevList() -> Element {
         let buttons = devices.iter().cloned().map(|dev| {
             let text = if dev.selected {
                 format!("-- {} --", dev.name)
+            } else if dev.volume_for.is_some() {
+                format!("{} (volume hand)", dev.name)
             } else {
                 dev.name
             };
@@ -182,41 +330,1172 @@ fn ThereminList() -> Element {
                 .filter(|d| d.selected)
                 .zip(theremin_positions.read().iter().cloned())
             {
-                div {
-                    div {
-                        "{dev.name}"
+                Theremin {
+                    dev: dev.clone(),
+                    pos: pos,
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn Theremin(dev: Dev, pos: f32) -> Element {
+    let theramin_msg_tx: Signal<TheraminMsgTx> = use_context();
+    let id = dev.id;
+    let settings = dev.settings;
+    let scroll_feedback = match settings.scroll {
+        ScrollAction::Off => String::new(),
+        ScrollAction::Octave | ScrollAction::Transpose => format!("Transpose {:+}", dev.transpose),
+        ScrollAction::Velocity => format!("Velocity {}", dev.velocity),
+        ScrollAction::Cc(cc) => format!("CC {cc}: {}", dev.scroll_cc_value),
+    };
+    rsx! {
+        div {
+            div {
+                "{dev.name}"
+            },
+            DevSettingsPanel {
+                dev: dev.clone(),
+            },
+            VolumeHand {
+                dev: dev.clone(),
+            },
+            MergePanel {
+                dev: dev.clone(),
+            },
+            NoteBar {
+                note_width: 4.0, // TODO be able to change
+                note_scroll: pos,
+                transpose: dev.transpose,
+                scroll_feedback: scroll_feedback,
+                slots: dev.tuning.slots().to_vec(),
+                hysteresis: settings.hysteresis,
+                note_widths: settings.note_widths,
+                on_note_widths: move |note_widths| {
+                    theramin_msg_tx
+                        .read()
+                        .send(Msg::SetDevSettings(id, DevSettings { note_widths, ..settings }));
+                },
+            },
+        }
+    }
+}
+
+/// Picks a device that isn't playing notes itself to play this one's volume with its Y axis
+#[component]
+fn VolumeHand(dev: Dev) -> Element {
+    let theramin_msg_tx: Signal<TheraminMsgTx> = use_context();
+    let devices: Signal<Devices> = use_context();
+    let id = dev.id;
+    let (volume_id, controller) = match dev.volume_hand {
+        Some((volume_id, controller)) => (volume_id.to_string(), controller),
+        None => ("none".to_string(), Controller::Cc(DEFAULT_VOLUME_CC)),
+    };
+    let (controller_kind, controller_num) = match controller {
+        Controller::Cc(cc) => ("cc", cc as u16),
+        Controller::Cc14(cc) => ("cc14", cc as u16),
+        Controller::Nrpn(param) => ("nrpn", param),
+    };
+    let pair = move |volume: Option<(usize, Controller)>| {
+        theramin_msg_tx.read().send(Msg::PairVolume(id, volume));
+    };
+    let volume_hand = dev.volume_hand;
+    rsx! {
+        div {
+            "Volume hand: ",
+            select {
+                value: "{volume_id}",
+                onchange: move |ev| {
+                    pair(ev.value().parse().ok().map(|volume_id| (volume_id, controller)));
+                },
+                option { value: "none", "None" },
+                for other in devices
+                    .read()
+                    .iter()
+                    .filter(|d| !d.selected && d.id != id && d.volume_hand.is_none())
+                {
+                    option {
+                        value: "{other.id}",
+                        "{other.name}"
+                    }
+                }
+            },
+            if let Some((volume_id, _)) = volume_hand {
+                select {
+                    value: controller_kind,
+                    onchange: move |ev| {
+                        let controller = controller_from(&ev.value(), controller_num);
+                        pair(controller.map(|controller| (volume_id, controller)));
+                    },
+                    option { value: "cc", "CC" },
+                    option { value: "cc14", "14-bit CC" },
+                    option { value: "nrpn", "NRPN" },
+                },
+                input {
+                    "type": "number",
+                    min: "0",
+                    value: "{controller_num}",
+                    onchange: move |ev| {
+                        if let Ok(num) = ev.value().parse() {
+                            let controller = controller_from(controller_kind, num);
+                            pair(controller.map(|controller| (volume_id, controller)));
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Plays this device through another one's MIDI port, or picks how devices merged into this
+/// one share it
+#[component]
+fn MergePanel(dev: Dev) -> Element {
+    let theramin_msg_tx: Signal<TheraminMsgTx> = use_context();
+    let devices: Signal<Devices> = use_context();
+    let id = dev.id;
+    let settings = dev.settings;
+    if let Some(into) = dev.merged_into {
+        let into_name = devices
+            .read()
+            .get(into)
+            .map(|d| d.name.clone())
+            .unwrap_or_default();
+        return rsx! {
+            div {
+                "Playing through {into_name} ",
+                button {
+                    "type": "button",
+                    onclick: move |_| theramin_msg_tx.read().send(Msg::MergeInto(id, None)),
+                    "Split"
+                },
+            }
+        };
+    }
+    let merge_policy = match settings.merge_policy {
+        MergePolicy::LastPressed => "last",
+        MergePolicy::HighestNote => "highest",
+        MergePolicy::All => "all",
+    };
+    rsx! {
+        div {
+            "Merge into: ",
+            select {
+                value: "none",
+                onchange: move |ev| {
+                    if let Ok(into) = ev.value().parse() {
+                        theramin_msg_tx.read().send(Msg::MergeInto(id, Some(into)));
+                    }
+                },
+                option { value: "none", "None" },
+                for other in devices
+                    .read()
+                    .iter()
+                    .filter(|d| d.selected && d.id != id && d.merged_into.is_none())
+                {
+                    option {
+                        value: "{other.id}",
+                        "{other.name}"
+                    }
+                }
+            },
+            if !dev.merged_from.is_empty() {
+                " When several are pressed: ",
+                select {
+                    value: merge_policy,
+                    onchange: move |ev| {
+                        let merge_policy = match ev.value().as_str() {
+                            "highest" => MergePolicy::HighestNote,
+                            "all" => MergePolicy::All,
+                            _ => MergePolicy::LastPressed,
+                        };
+                        theramin_msg_tx
+                            .read()
+                            .send(Msg::SetDevSettings(id, DevSettings { merge_policy, ..settings }));
+                    },
+                    option { value: "last", "Last pressed wins" },
+                    option { value: "highest", "Highest note wins" },
+                    option { value: "all", "All sound" },
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn DevSettingsPanel(dev: Dev) -> Element {
+    let theramin_msg_tx: Signal<TheraminMsgTx> = use_context();
+    let id = dev.id;
+    let settings = dev.settings;
+    let set = move |settings: DevSettings| {
+        theramin_msg_tx
+            .read()
+            .send(Msg::SetDevSettings(id, settings));
+    };
+    let mapping = match settings.mapping {
+        MappingMode::Relative => "relative",
+        MappingMode::Absolute => "absolute",
+    };
+    let voice_routing = match settings.voice_routing {
+        VoiceRouting::SingleChannel => "single",
+        VoiceRouting::MemberChannels => "member",
+        VoiceRouting::Mpe => "mpe",
+    };
+    let ump_path = ump_file_path(&dev.name).display().to_string();
+    let (controller_kind, controller_num) = match settings.y_controller {
+        None => ("off", 0),
+        Some(Controller::Cc(cc)) => ("cc", cc as u16),
+        Some(Controller::Cc14(cc)) => ("cc14", cc as u16),
+        Some(Controller::Nrpn(param)) => ("nrpn", param),
+    };
+    let tuning_name = dev
+        .tuning
+        .name
+        .clone()
+        .unwrap_or_else(|| "12 tone equal".to_string());
+    let learn_name = |target: LearnTarget| {
+        LEARN_TARGETS
+            .iter()
+            .find(|(_, _, t)| *t == target)
+            .map_or("", |(_, name, _)| name)
+    };
+    let learning = dev.learning.map(learn_name);
+    let learned: Vec<(LearnTarget, &str, String)> = dev
+        .learned
+        .iter()
+        .map(|&(target, source)| {
+            let source = match source {
+                LearnSource::Cc(cc) => format!("CC {cc}"),
+                LearnSource::Note(note) => format!("note {note}"),
+            };
+            (target, learn_name(target), source)
+        })
+        .collect();
+    let follow_keys = match settings.follow_keys {
+        FollowKeys::Off => "off",
+        FollowKeys::Root => "root",
+        FollowKeys::Chord => "chord",
+    };
+    let chord = match settings.chord {
+        ChordMode::Off => "off",
+        ChordMode::Power => "power",
+        ChordMode::Triad => "triad",
+        ChordMode::Seventh => "seventh",
+        ChordMode::Fixed(_) => "fixed",
+    };
+    let arp_params = match settings.arp {
+        None => None,
+        Some(arp) => {
+            let pattern = match arp.pattern {
+                ArpPattern::Up => "up",
+                ArpPattern::Down => "down",
+                ArpPattern::UpDown => "up_down",
+                ArpPattern::Random => "random",
+            };
+            let set_arp = move |arp: Arp| {
+                set(DevSettings {
+                    arp: Some(arp),
+                    ..settings
+                })
+            };
+            let tempo_params = match arp.tempo {
+                ArpTempo::MidiClock => rsx! {
+                    " from the \"{CLOCK_PORT_NAME}\" port"
+                },
+                ArpTempo::Internal(bpm) => rsx! {
+                    NumberInput {
+                        value: bpm,
+                        onchange: move |bpm: f32| {
+                            set_arp(Arp { tempo: ArpTempo::Internal(bpm.clamp(20.0, 300.0)), ..arp });
+                        },
+                    },
+                    " bpm"
+                },
+            };
+            rsx! {
+                " ",
+                select {
+                    value: pattern,
+                    onchange: move |ev| {
+                        let pattern = match ev.value().as_str() {
+                            "down" => ArpPattern::Down,
+                            "up_down" => ArpPattern::UpDown,
+                            "random" => ArpPattern::Random,
+                            _ => ArpPattern::Up,
+                        };
+                        set_arp(Arp { pattern, ..arp });
+                    },
+                    option { value: "up", "Up" },
+                    option { value: "down", "Down" },
+                    option { value: "up_down", "Up and down" },
+                    option { value: "random", "Random" },
+                },
+                " steps a beat ",
+                select {
+                    value: "{arp.steps_per_beat}",
+                    onchange: move |ev| {
+                        if let Ok(steps_per_beat) = ev.value().parse() {
+                            set_arp(Arp { steps_per_beat, ..arp });
+                        }
+                    },
+                    for steps in [1, 2, 3, 4, 6, 8] {
+                        option { value: "{steps}", "{steps}" }
+                    }
+                },
+                " tempo ",
+                select {
+                    value: if arp.tempo == ArpTempo::MidiClock { "clock" } else { "internal" },
+                    onchange: move |ev| {
+                        let tempo = match ev.value().as_str() {
+                            "clock" => ArpTempo::MidiClock,
+                            _ => ArpTempo::Internal(DEFAULT_BPM),
+                        };
+                        set_arp(Arp { tempo, ..arp });
+                    },
+                    option { value: "internal", "Internal" },
+                    option { value: "clock", "MIDI clock" },
+                },
+                {tempo_params}
+            }
+        }
+    };
+    let quantize = match settings.quantize {
+        None => "off",
+        Some(Grid::Eighth) => "eighth",
+        Some(Grid::EighthTriplet) => "eighth_triplet",
+        Some(Grid::Sixteenth) => "sixteenth",
+        Some(Grid::SixteenthTriplet) => "sixteenth_triplet",
+    };
+    let hold = match settings.hold {
+        HoldMode::Off => "off",
+        HoldMode::Latch => "latch",
+        HoldMode::HoldLast => "hold_last",
+        HoldMode::Pedal => "pedal",
+    };
+    let (scroll, scroll_cc) = match settings.scroll {
+        ScrollAction::Off => ("off", DEFAULT_SCROLL_CC),
+        ScrollAction::Octave => ("octave", DEFAULT_SCROLL_CC),
+        ScrollAction::Transpose => ("transpose", DEFAULT_SCROLL_CC),
+        ScrollAction::Velocity => ("velocity", DEFAULT_SCROLL_CC),
+        ScrollAction::Cc(cc) => ("cc", cc),
+    };
+    let smoothing_kind = match settings.smoothing {
+        Smoothing::Off => "off",
+        Smoothing::Ema { .. } => "ema",
+        Smoothing::OneEuro { .. } => "one_euro",
+    };
+    let curve_kind = match settings.curve {
+        ResponseCurve::Linear => "linear",
+        ResponseCurve::Exponential(_) => "exponential",
+        ResponseCurve::Custom(_) => "custom",
+    };
+    let smoothing_params = match settings.smoothing {
+        Smoothing::Off => None,
+        Smoothing::Ema { time_constant_ms } => rsx! {
+            " Time constant (ms): ",
+            NumberInput {
+                value: time_constant_ms,
+                onchange: move |time_constant_ms: f32| {
+                    let smoothing = Smoothing::Ema {
+                        time_constant_ms: time_constant_ms.max(0.0),
+                    };
+                    set(DevSettings { smoothing, ..settings });
+                },
+            }
+        },
+        Smoothing::OneEuro { min_cutoff, beta } => rsx! {
+            " Min cutoff (Hz): ",
+            NumberInput {
+                value: min_cutoff,
+                onchange: move |min_cutoff: f32| {
+                    let smoothing = Smoothing::OneEuro {
+                        min_cutoff: min_cutoff.max(0.0),
+                        beta,
+                    };
+                    set(DevSettings { smoothing, ..settings });
+                },
+            },
+            " Beta: ",
+            NumberInput {
+                value: beta,
+                onchange: move |beta: f32| {
+                    let smoothing = Smoothing::OneEuro {
+                        min_cutoff,
+                        beta: beta.max(0.0),
+                    };
+                    set(DevSettings { smoothing, ..settings });
+                },
+            }
+        },
+    };
+    let curve_params = match settings.curve {
+        ResponseCurve::Linear => None,
+        ResponseCurve::Exponential(exponent) => rsx! {
+            " Exponent: ",
+            NumberInput {
+                value: exponent,
+                onchange: move |exponent: f32| {
+                    let curve = ResponseCurve::Exponential(exponent.max(0.1));
+                    set(DevSettings { curve, ..settings });
+                },
+            }
+        },
+        ResponseCurve::Custom(points) => rsx! {
+            " Counts to distance: ",
+            for (i, (counts, distance)) in points.into_iter().enumerate() {
+                span {
+                    " (",
+                    NumberInput {
+                        value: counts,
+                        onchange: move |counts: f32| {
+                            let mut points = points;
+                            points[i].0 = counts.max(0.0);
+                            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                            let curve = ResponseCurve::Custom(points);
+                            set(DevSettings { curve, ..settings });
+                        },
+                    },
+                    ", ",
+                    NumberInput {
+                        value: distance,
+                        onchange: move |distance: f32| {
+                            let mut points = points;
+                            points[i].1 = distance.max(0.0);
+                            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                            let curve = ResponseCurve::Custom(points);
+                            set(DevSettings { curve, ..settings });
+                        },
+                    },
+                    ")"
+                }
+            }
+        },
+    };
+    let range = settings.range;
+    let edge = match settings.edge {
+        EdgeMode::Clamp => "clamp",
+        EdgeMode::Wrap => "wrap",
+        EdgeMode::Bounce => "bounce",
+    };
+    let tuning_output = match settings.tuning_output {
+        TuningOutput::PitchBend => "bend",
+        TuningOutput::Mts => "mts",
+    };
+    rsx! {
+        div {
+            "Mapping: ",
+            select {
+                value: mapping,
+                onchange: move |ev| {
+                    let mapping = match ev.value().as_str() {
+                        "absolute" => MappingMode::Absolute,
+                        _ => MappingMode::Relative,
+                    };
+                    set(DevSettings { mapping, ..settings });
+                },
+                option { value: "relative", "Relative (mouse)" },
+                option { value: "absolute", "Absolute (tablet/touchpad)" },
+            }
+        },
+        div {
+            button {
+                "type": "button",
+                onclick: move |_| set(DevSettings { polyphonic: !settings.polyphonic, ..settings }),
+                if settings.polyphonic {
+                    "Polyphonic (multitouch): on"
+                } else {
+                    "Polyphonic (multitouch): off"
+                }
+            },
+            " Voices on: ",
+            select {
+                value: voice_routing,
+                onchange: move |ev| {
+                    let voice_routing = match ev.value().as_str() {
+                        "member" => VoiceRouting::MemberChannels,
+                        "mpe" => VoiceRouting::Mpe,
+                        _ => VoiceRouting::SingleChannel,
+                    };
+                    set(DevSettings { voice_routing, ..settings });
+                },
+                option { value: "single", "Channel 1" },
+                option { value: "member", "Channels 2-16" },
+                option { value: "mpe", "MPE" },
+            }
+        },
+        div {
+            button {
+                "type": "button",
+                onclick: move |_| set(DevSettings { ump_file: !settings.ump_file, ..settings }),
+                if settings.ump_file {
+                    "MIDI 2.0 file: on"
+                } else {
+                    "MIDI 2.0 file: off"
+                }
+            },
+            if settings.ump_file {
+                " {ump_path}"
+            }
+        },
+        div {
+            "Y axis: ",
+            select {
+                value: controller_kind,
+                onchange: move |ev| {
+                    let y_controller = controller_from(&ev.value(), controller_num);
+                    set(DevSettings { y_controller, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "cc", "CC" },
+                option { value: "cc14", "14-bit CC" },
+                option { value: "nrpn", "NRPN" },
+            },
+            if settings.y_controller.is_some() {
+                input {
+                    "type": "number",
+                    min: "0",
+                    value: "{controller_num}",
+                    onchange: move |ev| {
+                        if let Ok(num) = ev.value().parse() {
+                            let y_controller = controller_from(controller_kind, num);
+                            set(DevSettings { y_controller, ..settings });
+                        }
+                    },
+                }
+            }
+        },
+        div {
+            "Tuning: {tuning_name} ",
+            label {
+                " .scl ",
+                input {
+                    "type": "file",
+                    accept: ".scl",
+                    onchange: move |ev| {
+                        for path in ev.files().into_iter().flat_map(|files| files.files()) {
+                            theramin_msg_tx.read().send(Msg::LoadScale(id, path.into()));
+                        }
+                    },
+                }
+            },
+            label {
+                " .kbm ",
+                input {
+                    "type": "file",
+                    accept: ".kbm",
+                    onchange: move |ev| {
+                        for path in ev.files().into_iter().flat_map(|files| files.files()) {
+                            theramin_msg_tx.read().send(Msg::LoadKeyboardMap(id, path.into()));
+                        }
                     },
-                    NoteBar {
-                        note_width: 4.0, // TODO be able to change
-                        note_scroll: pos,
+                }
+            },
+            if dev.tuning.name.is_some() {
+                button {
+                    "type": "button",
+                    onclick: move |_| theramin_msg_tx.read().send(Msg::ResetTuning(id)),
+                    "Reset"
+                }
+            }
+        },
+        div {
+            "Follow keys held on the \"{KEYS_PORT_NAME}\" port: ",
+            select {
+                value: follow_keys,
+                onchange: move |ev| {
+                    let follow_keys = match ev.value().as_str() {
+                        "root" => FollowKeys::Root,
+                        "chord" => FollowKeys::Chord,
+                        _ => FollowKeys::Off,
+                    };
+                    set(DevSettings { follow_keys, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "root", "Lowest key sets the root" },
+                option { value: "chord", "Only the keys held" },
+            }
+        },
+        div {
+            button {
+                "type": "button",
+                onclick: move |_| set(DevSettings { legato: !settings.legato, ..settings }),
+                if settings.legato {
+                    "Legato: on"
+                } else {
+                    "Legato: off"
+                }
+            },
+            button {
+                "type": "button",
+                onclick: move |_| {
+                    let portamento = match settings.portamento {
+                        Some(_) => None,
+                        None => Some(DEFAULT_GLIDE_TIME),
+                    };
+                    set(DevSettings { portamento, ..settings });
+                },
+                if settings.portamento.is_some() {
+                    "Portamento: on"
+                } else {
+                    "Portamento: off"
+                }
+            },
+            if let Some(glide_time) = settings.portamento {
+                " Glide time: ",
+                input {
+                    "type": "number",
+                    min: "0",
+                    max: "127",
+                    value: "{glide_time}",
+                    onchange: move |ev| {
+                        if let Ok(glide_time) = ev.value().parse::<u8>() {
+                            let portamento = Some(glide_time.min(127));
+                            set(DevSettings { portamento, ..settings });
+                        }
                     },
                 }
             }
+        },
+        div {
+            "Notes from ",
+            input {
+                "type": "number",
+                min: "0",
+                max: "{HIGHEST_MIDI_NOTE}",
+                value: "{range.lowest}",
+                onchange: move |ev| {
+                    if let Ok(lowest) = ev.value().parse::<u8>() {
+                        let lowest = lowest.min(range.highest);
+                        set(DevSettings { range: NoteRange { lowest, ..range }, ..settings });
+                    }
+                },
+            },
+            " to ",
+            input {
+                "type": "number",
+                min: "0",
+                max: "{HIGHEST_MIDI_NOTE}",
+                value: "{range.highest}",
+                onchange: move |ev| {
+                    if let Ok(highest) = ev.value().parse::<u8>() {
+                        let highest = highest.clamp(range.lowest, HIGHEST_MIDI_NOTE);
+                        set(DevSettings { range: NoteRange { highest, ..range }, ..settings });
+                    }
+                },
+            },
+            if settings.mapping == MappingMode::Relative {
+                " At the ends: ",
+                select {
+                    value: edge,
+                    onchange: move |ev| {
+                        let edge = match ev.value().as_str() {
+                            "wrap" => EdgeMode::Wrap,
+                            "bounce" => EdgeMode::Bounce,
+                            _ => EdgeMode::Clamp,
+                        };
+                        set(DevSettings { edge, ..settings });
+                    },
+                    option { value: "clamp", "Stop" },
+                    option { value: "wrap", "Wrap around" },
+                    option { value: "bounce", "Bounce" },
+                }
+            }
+        },
+        div {
+            "Smoothing: ",
+            select {
+                value: smoothing_kind,
+                onchange: move |ev| {
+                    let smoothing = match ev.value().as_str() {
+                        "ema" => DEFAULT_EMA,
+                        "one_euro" => DEFAULT_ONE_EURO,
+                        _ => Smoothing::Off,
+                    };
+                    set(DevSettings { smoothing, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "ema", "Moving average" },
+                option { value: "one_euro", "One euro" },
+            },
+            {smoothing_params}
+        },
+        if settings.mapping == MappingMode::Relative {
+            div {
+                "Response: ",
+                select {
+                    value: curve_kind,
+                    onchange: move |ev| {
+                        let curve = match ev.value().as_str() {
+                            "exponential" => ResponseCurve::Exponential(DEFAULT_EXPONENT),
+                            "custom" => ResponseCurve::Custom(DEFAULT_CURVE_POINTS),
+                            _ => ResponseCurve::Linear,
+                        };
+                        set(DevSettings { curve, ..settings });
+                    },
+                    option { value: "linear", "Linear" },
+                    option { value: "exponential", "Exponential" },
+                    option { value: "custom", "Custom" },
+                },
+                {curve_params}
+            }
+        },
+        if settings.mapping == MappingMode::Relative {
+            div {
+                "Sensitivity: ",
+                input {
+                    "type": "number",
+                    min: "{Sensitivity::MIN}",
+                    max: "{Sensitivity::MAX}",
+                    step: "0.25",
+                    value: "{settings.sensitivity.0}",
+                    onchange: move |ev| {
+                        if let Ok(value) = ev.value().parse::<f32>() {
+                            let sensitivity =
+                                Sensitivity(value.clamp(Sensitivity::MIN, Sensitivity::MAX));
+                            set(DevSettings { sensitivity, ..settings });
+                        }
+                    },
+                }
+            }
+        },
+        div {
+            "Hysteresis: ",
+            input {
+                "type": "number",
+                min: "0",
+                max: "0.5",
+                step: "0.05",
+                value: "{settings.hysteresis}",
+                onchange: move |ev| {
+                    if let Ok(hysteresis) = ev.value().parse::<f32>() {
+                        let hysteresis = hysteresis.clamp(0.0, 0.5);
+                        set(DevSettings { hysteresis, ..settings });
+                    }
+                },
+            }
+        },
+        div {
+            "Microtones by: ",
+            select {
+                value: tuning_output,
+                onchange: move |ev| {
+                    let tuning_output = match ev.value().as_str() {
+                        "mts" => TuningOutput::Mts,
+                        _ => TuningOutput::PitchBend,
+                    };
+                    set(DevSettings { tuning_output, ..settings });
+                },
+                option { value: "bend", "Pitch bend" },
+                option { value: "mts", "MIDI Tuning Standard" },
+            }
+        },
+        div {
+            "Chord: ",
+            select {
+                value: chord,
+                onchange: move |ev| {
+                    let chord = match ev.value().as_str() {
+                        "power" => ChordMode::Power,
+                        "triad" => ChordMode::Triad,
+                        "seventh" => ChordMode::Seventh,
+                        "fixed" => ChordMode::Fixed(DEFAULT_FIXED_CHORD),
+                        _ => ChordMode::Off,
+                    };
+                    set(DevSettings { chord, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "power", "Power" },
+                option { value: "triad", "Triad" },
+                option { value: "seventh", "Seventh" },
+                option { value: "fixed", "Fixed intervals" },
+            },
+            if let ChordMode::Fixed(intervals) = settings.chord {
+                " semitones:",
+                for (i, interval) in intervals.into_iter().enumerate() {
+                    IntervalInput {
+                        value: interval,
+                        onchange: move |interval| {
+                            let mut intervals = intervals;
+                            intervals[i] = interval;
+                            set(DevSettings { chord: ChordMode::Fixed(intervals), ..settings });
+                        },
+                    }
+                }
+            },
+            " Harmony, degrees:",
+            for (i, voice) in settings.harmony.0.into_iter().enumerate() {
+                IntervalInput {
+                    value: voice,
+                    onchange: move |voice| {
+                        let mut harmony = settings.harmony;
+                        harmony.0[i] = voice;
+                        set(DevSettings { harmony, ..settings });
+                    },
+                }
+            }
+        },
+        div {
+            button {
+                "type": "button",
+                onclick: move |_| {
+                    let arp = match settings.arp {
+                        Some(_) => None,
+                        None => Some(Arp::default()),
+                    };
+                    set(DevSettings { arp, ..settings });
+                },
+                if settings.arp.is_some() {
+                    "Arpeggiator: on"
+                } else {
+                    "Arpeggiator: off"
+                }
+            },
+            {arp_params}
+        },
+        div {
+            "Quantize new notes to the \"{CLOCK_PORT_NAME}\" port: ",
+            select {
+                value: quantize,
+                onchange: move |ev| {
+                    let quantize = match ev.value().as_str() {
+                        "eighth" => Some(Grid::Eighth),
+                        "eighth_triplet" => Some(Grid::EighthTriplet),
+                        "sixteenth" => Some(Grid::Sixteenth),
+                        "sixteenth_triplet" => Some(Grid::SixteenthTriplet),
+                        _ => None,
+                    };
+                    set(DevSettings { quantize, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "eighth", "1/8" },
+                option { value: "eighth_triplet", "1/8 triplet" },
+                option { value: "sixteenth", "1/16" },
+                option { value: "sixteenth_triplet", "1/16 triplet" },
+            }
+        },
+        div {
+            "When let go: ",
+            select {
+                value: hold,
+                onchange: move |ev| {
+                    let hold = match ev.value().as_str() {
+                        "latch" => HoldMode::Latch,
+                        "hold_last" => HoldMode::HoldLast,
+                        "pedal" => HoldMode::Pedal,
+                        _ => HoldMode::Off,
+                    };
+                    set(DevSettings { hold, ..settings });
+                },
+                option { value: "off", "Stop" },
+                option { value: "latch", "Keep playing, click again to stop" },
+                option { value: "hold_last", "Hold the last note" },
+                option { value: "pedal", "Hold while sustain is down" },
+            }
+        },
+        div {
+            "Scroll wheel: ",
+            select {
+                value: scroll,
+                onchange: move |ev| {
+                    let scroll = match ev.value().as_str() {
+                        "octave" => ScrollAction::Octave,
+                        "transpose" => ScrollAction::Transpose,
+                        "velocity" => ScrollAction::Velocity,
+                        "cc" => ScrollAction::Cc(scroll_cc),
+                        _ => ScrollAction::Off,
+                    };
+                    set(DevSettings { scroll, ..settings });
+                },
+                option { value: "off", "Off" },
+                option { value: "octave", "Octave" },
+                option { value: "transpose", "Transpose" },
+                option { value: "velocity", "Velocity" },
+                option { value: "cc", "CC" },
+            },
+            if let ScrollAction::Cc(cc) = settings.scroll {
+                input {
+                    "type": "number",
+                    min: "0",
+                    max: "127",
+                    value: "{cc}",
+                    onchange: move |ev| {
+                        if let Ok(cc) = ev.value().parse::<u8>() {
+                            let scroll = ScrollAction::Cc(cc.min(127));
+                            set(DevSettings { scroll, ..settings });
+                        }
+                    },
+                }
+            }
+        },
+        div {
+            "Buttons:",
+            for (button, bound) in settings.buttons.0.into_iter().enumerate() {
+                label {
+                    " {button + 1} ",
+                    select {
+                        value: BUTTON_ACTIONS
+                            .iter()
+                            .find(|(_, _, action)| *action == bound)
+                            .map_or("none", |(value, _, _)| value),
+                        onchange: move |ev| {
+                            let mut buttons = settings.buttons;
+                            buttons.0[button] = BUTTON_ACTIONS
+                                .iter()
+                                .find(|(value, _, _)| *value == ev.value())
+                                .and_then(|(_, _, action)| *action);
+                            set(DevSettings { buttons, ..settings });
+                        },
+                        for (value, name, _) in BUTTON_ACTIONS {
+                            option { value: value, "{name}" }
+                        }
+                    }
+                }
+            }
+        },
+        div {
+            "Presets: ",
+            for preset in 0..dev.presets {
+                button {
+                    "type": "button",
+                    disabled: dev.preset == Some(preset),
+                    onclick: move |_| theramin_msg_tx.read().send(Msg::LoadPreset(id, preset)),
+                    "{preset + 1}"
+                }
+                button {
+                    "type": "button",
+                    onclick: move |_| theramin_msg_tx.read().send(Msg::DeletePreset(id, preset)),
+                    "x"
+                }
+            }
+            " ",
+            button {
+                "type": "button",
+                onclick: move |_| theramin_msg_tx.read().send(Msg::SavePreset(id)),
+                "Save preset"
+            }
+        },
+        div {
+            "MIDI learn: ",
+            if let Some(name) = learning {
+                "Move a control on the \"{CONTROL_PORT_NAME}\" port for {name} ",
+                button {
+                    "type": "button",
+                    onclick: move |_| theramin_msg_tx.read().send(Msg::Learn(id, None)),
+                    "Cancel"
+                }
+            } else {
+                select {
+                    value: "none",
+                    onchange: move |ev| {
+                        let target = LEARN_TARGETS
+                            .iter()
+                            .find(|(value, _, _)| *value == ev.value())
+                            .map(|(_, _, target)| *target);
+                        theramin_msg_tx.read().send(Msg::Learn(id, target));
+                    },
+                    option { value: "none", "Learn..." },
+                    for (value, name, _) in LEARN_TARGETS {
+                        option { value: value, "{name}" }
+                    }
+                }
+            },
+            for (target, name, source) in learned {
+                div {
+                    "{name} on {source} ",
+                    button {
+                        "type": "button",
+                        onclick: move |_| theramin_msg_tx.read().send(Msg::Forget(id, target)),
+                        "Forget"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A small number box that only reports values that parse
+#[component]
+fn NumberInput(value: f32, onchange: EventHandler<f32>) -> Element {
+    rsx! {
+        input {
+            "type": "number",
+            width: "4em",
+            value: "{value}",
+            onchange: move |ev| {
+                if let Ok(value) = ev.value().parse() {
+                    onchange.call(value);
+                }
+            },
         }
     }
 }
 
+/// Left empty for no interval
+#[component]
+fn IntervalInput(value: Option<i8>, onchange: EventHandler<Option<i8>>) -> Element {
+    let shown = value.map(|value| value.to_string()).unwrap_or_default();
+    rsx! {
+        input {
+            "type": "number",
+            width: "3em",
+            min: "-24",
+            max: "24",
+            value: "{shown}",
+            onchange: move |ev| onchange.call(ev.value().parse::<i8>().ok().map(|v| v.clamp(-24, 24))),
+        }
+    }
+}
+
+/// Clamps `num` into the range the kind of controller allows
+fn controller_from(kind: &str, num: u16) -> Option<Controller> {
+    match kind {
+        "cc" => Some(Controller::Cc(num.min(127) as u8)),
+        "cc14" => Some(Controller::Cc14(num.min(31) as u8)),
+        "nrpn" => Some(Controller::Nrpn(num.min(0x3fff))),
+        _ => None,
+    }
+}
+
+/// A slot being resized in the layout editor
+#[derive(Clone, Copy, PartialEq)]
+struct WidthDrag {
+    key: Pitch,
+    start_x: f64,
+    start_percent: u8,
+    percent: u8,
+}
+
 #[component]
-fn NoteBar(note_width: f32, note_scroll: f32) -> Element {
-    let offset = 50.0 - note_scroll * note_width;
+fn NoteBar(
+    note_width: f32,
+    note_scroll: f32,
+    // keys are labelled with the note they sound
+    transpose: i8,
+    scroll_feedback: String,
+    slots: Vec<Slot>,
+    hysteresis: f32,
+    note_widths: NoteWidths,
+    on_note_widths: EventHandler<NoteWidths>,
+) -> Element {
+    let mut editing = use_signal(|| false);
+    let mut drag: Signal<Option<WidthDrag>> = use_signal(|| None);
+    let mut bar_px = use_signal(|| 0.0);
+    // a drag is only sent once it's let go
+    let mut shown_widths = note_widths;
+    if let Some(drag) = *drag.read() {
+        shown_widths.set(drag.key, drag.percent);
+    }
+    let slot_widths: Vec<f32> = slots
+        .iter()
+        .map(|slot| note_width * shown_widths.get(slot.key) as f32 / 100.0)
+        .collect();
+    let scroll_slot = (note_scroll.max(0.0) as usize).min(slots.len().saturating_sub(1));
+    let scrolled = slot_widths[..scroll_slot].iter().sum::<f32>()
+        + (note_scroll - scroll_slot as f32).max(0.0)
+            * slot_widths.get(scroll_slot).copied().unwrap_or(0.0);
+    let offset = 50.0 - scrolled;
+    // shades the band either side of each boundary where the note is held
+    let band = hysteresis.clamp(0.0, 0.5) * 100.0;
+    let far_band = 100.0 - band;
+    let band_background = format!(
+        "linear-gradient(to right, #333366 {band}%, transparent {band}%, \
+        transparent {far_band}%, #333366 {far_band}%)"
+    );
+    let finish_drag = move |_: MouseEvent| {
+        let Some(finished) = drag.write().take() else {
+            return;
+        };
+        let mut note_widths = note_widths;
+        note_widths.set(finished.key, finished.percent);
+        on_note_widths.call(note_widths);
+    };
     rsx! {
+        div {
+            button {
+                "type": "button",
+                onclick: move |_| editing.toggle(),
+                if editing() {
+                    "Done editing widths"
+                } else {
+                    "Edit widths"
+                }
+            },
+            if editing() {
+                button {
+                    "type": "button",
+                    onclick: move |_| on_note_widths.call(NoteWidths::default()),
+                    "Reset widths"
+                }
+            },
+            " {scroll_feedback}"
+        },
         div {
             display: "block",
             overflow: "clip",
             white_space: "nowrap",
+            onmounted: move |ev| async move {
+                if let Ok(rect) = ev.get_client_rect().await {
+                    bar_px.set(rect.width());
+                }
+            },
+            onmousemove: move |ev| {
+                let Some(mut moving) = *drag.read() else {
+                    return;
+                };
+                if bar_px() <= 0.0 {
+                    return;
+                }
+                // pixels to percent of the bar, then to percent of a standard slot
+                let dx = ev.client_coordinates().x - moving.start_x;
+                let change = dx / bar_px() * 100.0 / note_width as f64 * 100.0;
+                let percent = (moving.start_percent as f64 + change)
+                    .clamp(MIN_WIDTH_PERCENT as f64, MAX_WIDTH_PERCENT as f64);
+                moving.percent = percent.round() as u8;
+                drag.set(Some(moving));
+            },
+            onmouseup: finish_drag,
+            onmouseleave: finish_drag,
             div {
                 margin_left: "{offset}%",
                 display: "inline",
             }
-            for note in 0..(HIGHEST_MIDI_NOTE+1) {
+            for (slot, width) in slots.into_iter().zip(slot_widths) {
                 div {
-                    width: "{note_width}%",
+                    width: "{width}%",
                     box_sizing: "border-box",
                     border: "solid grey",
+                    background: "{band_background}",
                     text_align: "center",
                     display: "inline-block",
                     white_space: "nowrap",
-                    "{note}"
+                    overflow: "hidden",
+                    if editing() {
+                        div {
+                            float: "right",
+                            width: "4px",
+                            height: "1em",
+                            background_color: "white",
+                            cursor: "col-resize",
+                            onmousedown: move |ev| {
+                                let percent = shown_widths.get(slot.key);
+                                drag.set(Some(WidthDrag {
+                                    key: slot.key,
+                                    start_x: ev.client_coordinates().x,
+                                    start_percent: percent,
+                                    percent,
+                                }));
+                            },
+                        }
+                    },
+                    // the nearest note to what the slot sounds, a keyboard map can put any key there
+                    "{slot.pitch.round() as i16 + transpose as i16}",
+                    if let Some((_, cents)) = slot.degree {
+                        div {
+                            font_size: "x-small",
+                            "{cents:.0}¢"
+                        }
+                    }
                 }
             },
             div {
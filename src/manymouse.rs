@@ -1,28 +1,59 @@
 use std::{
     ffi::{c_char, c_int, c_uint, CStr},
     mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+use crate::error::{Result, TheraminError};
+
 extern "C" {
     fn ManyMouse_Init() -> c_int;
     fn ManyMouse_DriverName() -> *const c_char;
     fn ManyMouse_Quit();
     fn ManyMouse_DeviceName(index: c_uint) -> *const c_char;
-    fn ManyMouse_PollEvent(event: *mut Event) -> c_int;
+    fn ManyMouse_PollEvent(event: *mut RawEvent) -> c_int;
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+/// ManyMouse keeps global state in C, so only one `ManyMouse` may be alive at a time
+static INSTANCE_LIVE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventType {
-    Absmotion = 0,
+    Absmotion,
     Relmotion,
     Button,
     Scroll,
     Disconnect,
-    Max,
 }
 
+impl TryFrom<c_int> for EventType {
+    type Error = c_int;
+
+    fn try_from(value: c_int) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EventType::Absmotion),
+            1 => Ok(EventType::Relmotion),
+            2 => Ok(EventType::Button),
+            3 => Ok(EventType::Scroll),
+            4 => Ok(EventType::Disconnect),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Layout of `ManyMouseEvent`, the type is left as a plain int so that unknown values from C
+/// never end up in an `EventType`
 #[repr(C)]
+#[derive(Clone, Copy)]
+struct RawEvent {
+    ev_type: c_int,
+    device: c_uint,
+    item: c_uint,
+    value: c_int,
+    minval: c_int,
+    maxval: c_int,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Event {
     pub ev_type: EventType,
@@ -33,6 +64,21 @@ pub struct Event {
     pub maxval: c_int,
 }
 
+impl TryFrom<RawEvent> for Event {
+    type Error = c_int;
+
+    fn try_from(raw: RawEvent) -> std::result::Result<Self, Self::Error> {
+        Ok(Event {
+            ev_type: raw.ev_type.try_into()?,
+            device: raw.device,
+            item: raw.item,
+            value: raw.value,
+            minval: raw.minval,
+            maxval: raw.maxval,
+        })
+    }
+}
+
 pub enum Axis {
     X = 0,
     Y,
@@ -51,12 +97,17 @@ impl<'a> Iterator for EventIter<'a> {
     type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            let mut ev = MaybeUninit::uninit();
-            if ManyMouse_PollEvent(ev.as_mut_ptr()) != 0 {
-                Some(ev.assume_init())
-            } else {
-                None
+        loop {
+            let raw = unsafe {
+                let mut ev = MaybeUninit::<RawEvent>::uninit();
+                if ManyMouse_PollEvent(ev.as_mut_ptr()) == 0 {
+                    return None;
+                }
+                ev.assume_init()
+            };
+            // skip events with a type we don't know about
+            if let Ok(ev) = Event::try_from(raw) {
+                return Some(ev);
             }
         }
     }
@@ -67,16 +118,24 @@ pub struct ManyMouse {
 }
 
 impl ManyMouse {
-    pub fn new() -> Self {
+    /// Fails if another `ManyMouse` is still alive
+    pub fn new() -> Result<Self> {
+        if INSTANCE_LIVE
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(TheraminError::ManyMouseInUse);
+        }
         unsafe {
             let available_mice = ManyMouse_Init();
-            if available_mice == -1 {
+            if available_mice < 0 {
                 ManyMouse_Quit();
-                panic!("ManyMouse couldn't initialize");
+                INSTANCE_LIVE.store(false, Ordering::Release);
+                return Err(TheraminError::ManyMouseInit);
             }
-            Self {
+            Ok(Self {
                 avail_mice_len: available_mice as u32,
-            }
+            })
         }
     }
 
@@ -84,24 +143,29 @@ impl ManyMouse {
         EventIter { _mm: self }
     }
 
-    pub fn device_list(&self) -> Vec<String> {
-        unsafe {
-            (0..self.avail_mice_len)
-                .map(|i| {
-                    CStr::from_ptr(ManyMouse_DeviceName(i))
-                        .to_string_lossy()
-                        .to_string()
-                })
-                .collect()
+    pub fn device_count(&self) -> u32 {
+        self.avail_mice_len
+    }
+
+    /// Returns `None` for indices ManyMouse didn't report at init
+    pub fn device_name(&self, index: u32) -> Option<String> {
+        if index >= self.avail_mice_len {
+            return None;
         }
+        unsafe { string_from_c(ManyMouse_DeviceName(index)) }
+    }
+
+    pub fn device_list(&self) -> Vec<String> {
+        (0..self.avail_mice_len)
+            .map(|i| {
+                self.device_name(i)
+                    .unwrap_or_else(|| format!("Unknown device {}", i))
+            })
+            .collect()
     }
 
     pub fn driver_name(&self) -> String {
-        unsafe {
-            CStr::from_ptr(ManyMouse_DriverName())
-                .to_string_lossy()
-                .to_string()
-        }
+        unsafe { string_from_c(ManyMouse_DriverName()) }.unwrap_or_default()
     }
 }
 
@@ -110,5 +174,16 @@ impl Drop for ManyMouse {
         unsafe {
             ManyMouse_Quit();
         }
+        INSTANCE_LIVE.store(false, Ordering::Release);
+    }
+}
+
+/// # Safety
+/// `ptr` must be null or point to a nul terminated string
+unsafe fn string_from_c(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().to_string())
     }
 }
@@ -1,9 +1,45 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    time::{Duration, Instant},
+};
+
 use midir::{os::unix::VirtualOutput, MidiOutput, MidiOutputConnection, MidiOutputPort};
 
+use crate::error::{Result, TheraminError};
+
 pub const HIGHEST_MIDI_NOTE: u8 = 127;
-const VEL: u8 = 127;
-const NOTE_ON_MSG: u8 = 0x90;
-const NOTE_OFF_MSG: u8 = 0x80;
+pub const VEL: u8 = 127;
+pub(crate) const NOTE_ON_MSG: u8 = 0x90;
+pub(crate) const NOTE_OFF_MSG: u8 = 0x80;
+pub(crate) const CC_MSG: u8 = 0xB0;
+const CHANNEL_PRESSURE_MSG: u8 = 0xD0;
+const PITCH_BEND_MSG: u8 = 0xE0;
+const MIDI_CHANNELS: u8 = 16;
+
+const NRPN_MSB_CC: u8 = 99;
+const NRPN_LSB_CC: u8 = 98;
+const RPN_MSB_CC: u8 = 101;
+const RPN_LSB_CC: u8 = 100;
+const CC_LSB_OFFSET: u8 = 32;
+const DATA_ENTRY_MSB_CC: u8 = 6;
+const DATA_ENTRY_LSB_CC: u8 = 38;
+const TIMBRE_CC: u8 = 74;
+const PORTAMENTO_TIME_CC: u8 = 5;
+const PORTAMENTO_CC: u8 = 65;
+const SUSTAIN_CC: u8 = 64;
+const ALL_NOTES_OFF_CC: u8 = 123;
+const PITCH_BEND_RANGE_RPN: u8 = 0;
+const MPE_CONFIGURATION_RPN: u8 = 6;
+const NULL_RPN: u8 = 127;
+const PITCH_BEND_CENTER: u16 = 0x2000;
+/// Semitones either way, the default member channel range in the MPE spec
+pub const MPE_BEND_RANGE: u8 = 48;
+/// Semitones either way most synths start with, enough to reach any microtonal pitch from its
+/// nearest note
+const DEFAULT_BEND_RANGE: u8 = 2;
+/// Shortest gap between two messages for the same controller
+const CONTROLLER_INTERVAL: Duration = Duration::from_millis(5);
 
 pub type Pitch = u8;
 
@@ -11,72 +47,643 @@ pub struct MidiInitialiser {
     midi_out: MidiOutput,
 }
 
-pub struct MidiHandler {
-    current_note: Option<Pitch>,
-    conn_out: MidiOutputConnection,
+/// Which channel each voice of a `MidiHandler` plays on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoiceRouting {
+    #[default]
+    SingleChannel,
+    /// Voices get channels 2 to 16 in turn, the member channels of an MPE lower zone
+    MemberChannels,
+    /// An MPE lower zone, each sounding note gets a free member channel and its own expression
+    Mpe,
 }
 
-impl MidiInitialiser {
-    pub fn new() -> Self {
-        MidiInitialiser {
-            midi_out: MidiOutput::new("Theramin midi out").unwrap(),
+/// Per note expression, timbre and pressure are only sent when routing is `VoiceRouting::Mpe`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expression {
+    /// Semitones from the note, sent on the note's channel in any routing so tunings outside
+    /// 12 tone can be played. Voices sharing a channel share its bend
+    pub bend: f32,
+    /// 0 to 1, sent as CC74
+    pub timbre: f32,
+    /// 0 to 1, sent as channel pressure
+    pub pressure: f32,
+}
+
+impl Default for Expression {
+    fn default() -> Self {
+        Expression {
+            bend: 0.0,
+            timbre: 0.5,
+            pressure: 1.0,
         }
     }
+}
+
+/// A controller a mouse axis can be mapped to, sent on the first channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Controller {
+    Cc(u8),
+    /// MSB on CC n and LSB on CC n + 32, so n must be below 32
+    Cc14(u8),
+    /// 14 bit parameter and value
+    Nrpn(u16),
+}
+
+impl Controller {
+    fn max_value(self) -> u16 {
+        match self {
+            Controller::Cc(_) => 0x7f,
+            Controller::Cc14(_) | Controller::Nrpn(_) => 0x3fff,
+        }
+    }
+}
+
+/// Rate limiting state, values that arrive too soon are held in `pending` until `flush`
+#[derive(Default)]
+struct ControllerState {
+    last_sent: Option<Instant>,
+    sent: Option<u16>,
+    pending: Option<u16>,
+}
+
+#[derive(Clone, Copy)]
+struct Note {
+    pitch: Pitch,
+    channel: u8,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Voice {
+    note: Option<Note>,
+    expression: Expression,
+}
+
+/// Somewhere to send MIDI 1.0 messages
+pub trait MidiSink {
+    fn send(&mut self, msg: &[u8]) -> Result<()>;
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        MidiOutputConnection::send(self, msg)?;
+        Ok(())
+    }
+}
+
+/// Keeps every message, for tests or inspecting output
+impl MidiSink for Vec<Vec<u8>> {
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        self.push(msg.to_vec());
+        Ok(())
+    }
+}
+
+/// Voice 0 is the one used by `play` and `release`, extra voices are for polyphonic input
+pub struct MidiHandler<S: MidiSink = MidiOutputConnection> {
+    voices: Vec<Voice>,
+    routing: VoiceRouting,
+    /// Member channels not holding a note, least recently used first
+    free_channels: VecDeque<u8>,
+    controllers: HashMap<Controller, ControllerState>,
+    /// Last pitch bend value sent on each channel
+    channel_bends: [u16; MIDI_CHANNELS as usize],
+    /// Start a voice's next note before ending its last so envelopes don't retrigger
+    pub legato: bool,
+    /// Glide time sent as CC5 while portamento is on
+    portamento: Option<u8>,
+    /// For note ons, note offs are always sent with `VEL`
+    pub velocity: u8,
+    /// Notes let go of while the pedal is down, `None` while it's up
+    pedal_notes: Option<Vec<Note>>,
+    /// Set when a bend didn't fit the bend range, reported by the next flush
+    bend_clamped: bool,
+    sink: S,
+}
+
+impl MidiInitialiser {
+    pub fn new() -> Result<Self> {
+        Ok(MidiInitialiser {
+            midi_out: MidiOutput::new("Theramin midi out")?,
+        })
+    }
 
     pub fn from_output(midi_out: MidiOutput) -> Self {
         MidiInitialiser { midi_out }
     }
 
-    pub fn get_ports(&self) -> Vec<(String, MidiOutputPort)> {
+    pub fn get_ports(&self) -> Result<Vec<(String, MidiOutputPort)>> {
         self.midi_out
             .ports()
             .into_iter()
-            .map(|port| (self.midi_out.port_name(&port).unwrap(), port))
+            .map(|port| Ok((self.midi_out.port_name(&port)?, port)))
             .collect()
     }
 
-    pub fn virtual_port(self, name: &str) -> MidiHandler {
-        MidiHandler::new(self.midi_out.create_virtual(name).unwrap())
+    pub fn virtual_port(self, name: &str) -> Result<MidiHandler> {
+        Ok(MidiHandler::new(self.midi_out.create_virtual(name)?))
     }
 
-    pub fn connect(self, port: (String, &MidiOutputPort)) -> MidiHandler {
-        MidiHandler::new(self.midi_out.connect(port.1, &port.0).unwrap())
+    pub fn connect(self, port: (String, &MidiOutputPort)) -> Result<MidiHandler> {
+        Ok(MidiHandler::new(self.midi_out.connect(port.1, &port.0)?))
     }
 }
 
-impl MidiHandler {
-    fn new(conn_out: MidiOutputConnection) -> Self {
+impl<S: MidiSink> MidiHandler<S> {
+    fn new(sink: S) -> Self {
         MidiHandler {
-            current_note: None,
-            conn_out,
+            voices: vec![Voice::default()],
+            routing: VoiceRouting::default(),
+            free_channels: (1..MIDI_CHANNELS).collect(),
+            controllers: HashMap::new(),
+            channel_bends: [PITCH_BEND_CENTER; MIDI_CHANNELS as usize],
+            legato: false,
+            portamento: None,
+            velocity: VEL,
+            pedal_notes: None,
+            bend_clamped: false,
+            sink,
+        }
+    }
+
+    /// `value` goes from 0 to 1, it's dropped if it rounds to what was last sent and held
+    /// back if the controller was sent within `CONTROLLER_INTERVAL`
+    pub fn set_controller(&mut self, controller: Controller, value: f32) -> Result<()> {
+        let max = controller.max_value();
+        let value = (value.clamp(0.0, 1.0) * max as f32).round() as u16;
+        let state = self.controllers.entry(controller).or_default();
+        state.pending = (state.sent != Some(value)).then_some(value);
+        self.flush_controller(controller)
+    }
+
+    /// Sends held back controller values whose interval has passed, call this regularly.
+    /// Bends clamped since the last call are reported after, they're sent anyway so notes
+    /// aren't held up
+    pub fn flush_controllers(&mut self) -> Result<()> {
+        let pending: Vec<Controller> = self
+            .controllers
+            .iter()
+            .filter(|(_, state)| state.pending.is_some())
+            .map(|(controller, _)| *controller)
+            .collect();
+        pending
+            .into_iter()
+            .try_for_each(|controller| self.flush_controller(controller))?;
+        if std::mem::take(&mut self.bend_clamped) {
+            let range = match self.routing {
+                VoiceRouting::Mpe => MPE_BEND_RANGE,
+                _ => DEFAULT_BEND_RANGE,
+            };
+            return Err(TheraminError::BendRange(range));
+        }
+        Ok(())
+    }
+
+    fn flush_controller(&mut self, controller: Controller) -> Result<()> {
+        let Some(state) = self.controllers.get_mut(&controller) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let Some(value) = state.pending else {
+            return Ok(());
+        };
+        if state
+            .last_sent
+            .is_some_and(|last_sent| now.duration_since(last_sent) < CONTROLLER_INTERVAL)
+        {
+            return Ok(());
+        }
+        state.pending = None;
+        state.sent = Some(value);
+        state.last_sent = Some(now);
+        let (msb, lsb) = ((value >> 7) as u8, (value & 0x7f) as u8);
+        match controller {
+            Controller::Cc(cc) => self.sink.send(&[CC_MSG, cc, value as u8])?,
+            Controller::Cc14(cc) => {
+                self.sink.send(&[CC_MSG, cc, msb])?;
+                self.sink.send(&[CC_MSG, cc + CC_LSB_OFFSET, lsb])?;
+            }
+            Controller::Nrpn(param) => {
+                self.sink
+                    .send(&[CC_MSG, NRPN_MSB_CC, (param >> 7) as u8 & 0x7f])?;
+                self.sink
+                    .send(&[CC_MSG, NRPN_LSB_CC, (param & 0x7f) as u8])?;
+                self.sink.send(&[CC_MSG, DATA_ENTRY_MSB_CC, msb])?;
+                self.sink.send(&[CC_MSG, DATA_ENTRY_LSB_CC, lsb])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `msg` is a whole message from 0xF0 to 0xF7
+    pub fn send_sysex(&mut self, msg: &[u8]) -> Result<()> {
+        self.sink.send(msg)?;
+        Ok(())
+    }
+
+    pub fn play(&mut self, pitch: Pitch) -> Result<()> {
+        self.play_voice(0, pitch)
+    }
+
+    pub fn release(&mut self) -> Result<()> {
+        self.release_voice(0)
+    }
+
+    pub fn play_voice(&mut self, voice: usize, pitch: Pitch) -> Result<()> {
+        if voice >= self.voices.len() {
+            self.voices.resize(voice + 1, Voice::default());
+        }
+        let legato_from = match self.voices[voice].note {
+            Some(note) if note.pitch == pitch => return Ok(()),
+            Some(note) if self.legato => {
+                self.voices[voice].note = None;
+                Some(note)
+            }
+            Some(_) => {
+                self.release_voice(voice)?;
+                None
+            }
+            None => None,
+        };
+        let channel = self.next_channel(voice);
+        // expression goes first so the note starts in the right place
+        let expression = self.voices[voice].expression;
+        if self.routing == VoiceRouting::Mpe {
+            self.send_expression(channel, expression)?;
+        } else {
+            self.send_bend(channel, expression.bend)?;
+        }
+        self.restrike(voice, channel, pitch)?;
+        if !self.sounding_elsewhere(Some(voice), channel, pitch) {
+            self.sink
+                .send(&[NOTE_ON_MSG | channel, pitch, self.velocity])?;
+        }
+        self.voices[voice].note = Some(Note { pitch, channel });
+        match legato_from {
+            Some(note) => self.end_note(voice, note),
+            None => Ok(()),
+        }
+    }
+
+    pub fn voice_note(&self, voice: usize) -> Option<Pitch> {
+        self.voices.get(voice)?.note.map(|note| note.pitch)
+    }
+
+    pub fn release_voice(&mut self, voice: usize) -> Result<()> {
+        let Some(note) = self.voices.get_mut(voice).and_then(|v| v.note.take()) else {
+            return Ok(());
+        };
+        self.end_note(voice, note)
+    }
+
+    /// For a note `voice` has already let go of, it's kept on if the pedal is down
+    fn end_note(&mut self, voice: usize, note: Note) -> Result<()> {
+        match self.pedal_notes.as_mut() {
+            Some(pedal_notes) => {
+                pedal_notes.push(note);
+                Ok(())
+            }
+            None => self.stop_note(Some(voice), note),
+        }
+    }
+
+    /// Sends the note off unless a voice other than `voice` is still holding it
+    fn stop_note(&mut self, voice: Option<usize>, note: Note) -> Result<()> {
+        if self.routing == VoiceRouting::Mpe && !self.channel_in_use(note.channel) {
+            self.free_channels.push_back(note.channel);
+        }
+        if !self.sounding_elsewhere(voice, note.channel, note.pitch) {
+            self.sink
+                .send(&[NOTE_OFF_MSG | note.channel, note.pitch, VEL])?;
+        }
+        Ok(())
+    }
+
+    /// Takes a note the pedal's holding back off it and ends it, so playing it again doesn't
+    /// stack a second note on
+    fn restrike(&mut self, voice: usize, channel: u8, pitch: Pitch) -> Result<()> {
+        let Some(pedal_notes) = self.pedal_notes.as_mut() else {
+            return Ok(());
+        };
+        let held = pedal_notes.len();
+        pedal_notes.retain(|note| note.pitch != pitch || note.channel != channel);
+        if pedal_notes.len() != held && !self.sounding_elsewhere(Some(voice), channel, pitch) {
+            self.sink.send(&[NOTE_OFF_MSG | channel, pitch, VEL])?;
         }
+        Ok(())
     }
 
-    pub fn play(&mut self, pitch: Pitch) {
-        match self.current_note {
-            Some(current_note) if current_note == pitch => return,
-            Some(current_note) => {
-                self.conn_out
-                    .send(&[NOTE_OFF_MSG, current_note, VEL])
-                    .unwrap();
+    /// Notes held by the pedal too, though the pedal stays down
+    pub fn release_all(&mut self) -> Result<()> {
+        (0..self.voices.len()).try_for_each(|voice| self.release_voice(voice))?;
+        let pedal_notes = self.pedal_notes.as_mut().map(std::mem::take);
+        for note in pedal_notes.unwrap_or_default() {
+            self.stop_note(None, note)?;
+        }
+        Ok(())
+    }
+
+    /// While down, released notes keep sounding until it's lifted, for synths that ignore
+    /// CC64 or to sustain just this handler's notes
+    pub fn set_pedal(&mut self, down: bool) -> Result<()> {
+        if down {
+            self.pedal_notes.get_or_insert_with(Vec::new);
+            return Ok(());
+        }
+        for note in self.pedal_notes.take().unwrap_or_default() {
+            self.stop_note(None, note)?;
+        }
+        Ok(())
+    }
+
+    /// Sends whatever changed since the last call if the voice is sounding, otherwise it's kept
+    /// for the voice's next note
+    pub fn express_voice(&mut self, voice: usize, expression: Expression) -> Result<()> {
+        if voice >= self.voices.len() {
+            self.voices.resize(voice + 1, Voice::default());
+        }
+        let prev = std::mem::replace(&mut self.voices[voice].expression, expression);
+        let Some(note) = self.voices[voice].note else {
+            return Ok(());
+        };
+        let channel = note.channel;
+        self.send_bend(channel, expression.bend)?;
+        if self.routing != VoiceRouting::Mpe {
+            return Ok(());
+        }
+        if unit_to_7bit(prev.timbre) != unit_to_7bit(expression.timbre) {
+            self.sink
+                .send(&[CC_MSG | channel, TIMBRE_CC, unit_to_7bit(expression.timbre)])?;
+        }
+        if unit_to_7bit(prev.pressure) != unit_to_7bit(expression.pressure) {
+            self.sink.send(&[
+                CHANNEL_PRESSURE_MSG | channel,
+                unit_to_7bit(expression.pressure),
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Releases everything first so no note is left on its old channel, and sends the MPE
+    /// Configuration Message when entering or leaving MPE
+    pub fn set_routing(&mut self, routing: VoiceRouting) -> Result<()> {
+        if routing == self.routing {
+            return Ok(());
+        }
+        self.release_all()?;
+        if self.portamento.is_some() {
+            self.send_portamento(None)?;
+        }
+        if self.routing == VoiceRouting::Mpe {
+            self.send_mpe_configuration(0)?;
+            // member channels keep their MPE range otherwise and bends would overshoot
+            for channel in 1..MIDI_CHANNELS {
+                self.send_rpn(channel, PITCH_BEND_RANGE_RPN, DEFAULT_BEND_RANGE)?;
             }
-            None => (),
         }
-        self.conn_out.send(&[NOTE_ON_MSG, pitch, VEL]).unwrap();
-        self.current_note = Some(pitch);
+        self.routing = routing;
+        self.free_channels = (1..MIDI_CHANNELS).collect();
+        if routing == VoiceRouting::Mpe {
+            self.send_mpe_configuration(MIDI_CHANNELS - 1)?;
+            for channel in 1..MIDI_CHANNELS {
+                self.send_rpn(channel, PITCH_BEND_RANGE_RPN, MPE_BEND_RANGE)?;
+            }
+        }
+        // the notes have moved to other channels, so does the portamento
+        match self.portamento {
+            Some(glide_time) => self.send_portamento(Some(glide_time)),
+            None => Ok(()),
+        }
+    }
+
+    /// `Some(glide_time)` turns portamento on with CC65 and sets its time with CC5, `None`
+    /// turns it off
+    pub fn set_portamento(&mut self, portamento: Option<u8>) -> Result<()> {
+        if portamento == self.portamento {
+            return Ok(());
+        }
+        self.portamento = portamento;
+        self.send_portamento(portamento)
+    }
+
+    fn send_portamento(&mut self, portamento: Option<u8>) -> Result<()> {
+        for channel in self.voice_channels() {
+            if let Some(glide_time) = portamento {
+                self.sink
+                    .send(&[CC_MSG | channel, PORTAMENTO_TIME_CC, glide_time.min(0x7f)])?;
+            }
+            let on = if portamento.is_some() { 0x7f } else { 0 };
+            self.sink.send(&[CC_MSG | channel, PORTAMENTO_CC, on])?;
+        }
+        Ok(())
+    }
+
+    /// CC64 on every channel voices play on
+    pub fn set_sustain(&mut self, on: bool) -> Result<()> {
+        let value = if on { 0x7f } else { 0 };
+        for channel in self.voice_channels() {
+            self.sink.send(&[CC_MSG | channel, SUSTAIN_CC, value])?;
+        }
+        Ok(())
+    }
+
+    /// Lifts the pedal, releases every voice, then turns off sustain and sends all notes off on
+    /// every channel, for notes stuck on the synth whatever put them there
+    pub fn panic(&mut self) -> Result<()> {
+        self.set_pedal(false)?;
+        self.release_all()?;
+        for channel in 0..MIDI_CHANNELS {
+            self.sink.send(&[CC_MSG | channel, SUSTAIN_CC, 0])?;
+            self.sink.send(&[CC_MSG | channel, ALL_NOTES_OFF_CC, 0])?;
+        }
+        Ok(())
+    }
+
+    fn voice_channels(&self) -> Range<u8> {
+        match self.routing {
+            VoiceRouting::SingleChannel => 0..1,
+            VoiceRouting::MemberChannels | VoiceRouting::Mpe => 1..MIDI_CHANNELS,
+        }
+    }
+
+    /// Sets up a lower zone with its master on channel 1, zero member channels turns it off
+    fn send_mpe_configuration(&mut self, member_channels: u8) -> Result<()> {
+        self.send_rpn(0, MPE_CONFIGURATION_RPN, member_channels)
+    }
+
+    fn send_rpn(&mut self, channel: u8, rpn: u8, value: u8) -> Result<()> {
+        let cc = CC_MSG | channel;
+        self.sink.send(&[cc, RPN_MSB_CC, 0])?;
+        self.sink.send(&[cc, RPN_LSB_CC, rpn])?;
+        self.sink.send(&[cc, DATA_ENTRY_MSB_CC, value])?;
+        self.sink.send(&[cc, DATA_ENTRY_LSB_CC, 0])?;
+        self.sink.send(&[cc, RPN_MSB_CC, NULL_RPN])?;
+        self.sink.send(&[cc, RPN_LSB_CC, NULL_RPN])?;
+        Ok(())
+    }
+
+    fn send_expression(&mut self, channel: u8, expression: Expression) -> Result<()> {
+        self.send_bend(channel, expression.bend)?;
+        self.sink
+            .send(&[CC_MSG | channel, TIMBRE_CC, unit_to_7bit(expression.timbre)])?;
+        self.sink.send(&[
+            CHANNEL_PRESSURE_MSG | channel,
+            unit_to_7bit(expression.pressure),
+        ])?;
+        Ok(())
+    }
+
+    /// Skipped if the channel is already bent there
+    fn send_bend(&mut self, channel: u8, semitones: f32) -> Result<()> {
+        let range = match self.routing {
+            VoiceRouting::Mpe => MPE_BEND_RANGE,
+            _ => DEFAULT_BEND_RANGE,
+        };
+        if semitones.abs() > range as f32 {
+            self.bend_clamped = true;
+        }
+        let value = bend_value(semitones, range);
+        if self.channel_bends[channel as usize] == value {
+            return Ok(());
+        }
+        self.channel_bends[channel as usize] = value;
+        self.sink.send(&[
+            PITCH_BEND_MSG | channel,
+            (value & 0x7f) as u8,
+            (value >> 7) as u8,
+        ])?;
+        Ok(())
     }
 
-    pub fn release(&mut self) {
-        if let Some(current_note) = self.current_note {
-            self.conn_out
-                .send(&[NOTE_OFF_MSG, current_note, VEL])
-                .unwrap();
-            self.current_note = None;
+    fn next_channel(&mut self, voice: usize) -> u8 {
+        match self.routing {
+            VoiceRouting::SingleChannel => 0,
+            VoiceRouting::MemberChannels => 1 + (voice % (MIDI_CHANNELS - 1) as usize) as u8,
+            // if every member channel is busy double up on one by voice
+            VoiceRouting::Mpe => self
+                .free_channels
+                .pop_front()
+                .unwrap_or(1 + (voice % (MIDI_CHANNELS - 1) as usize) as u8),
         }
     }
 
-    pub fn close(mut self) -> MidiInitialiser {
-        self.release();
-        MidiInitialiser::from_output(self.conn_out.close())
+    /// Whether a doubled up voice or the pedal still has a note on `channel`, or it's already
+    /// been freed
+    fn channel_in_use(&self, channel: u8) -> bool {
+        let voice_notes = self.voices.iter().filter_map(|voice| voice.note);
+        let pedal_notes = self.pedal_notes.iter().flatten().copied();
+        self.free_channels.contains(&channel)
+            || voice_notes
+                .chain(pedal_notes)
+                .any(|note| note.channel == channel)
+    }
+
+    /// Whether another voice is holding the same note on the same channel
+    fn sounding_elsewhere(&self, voice: Option<usize>, channel: u8, pitch: Pitch) -> bool {
+        self.voices.iter().enumerate().any(|(v, other)| {
+            Some(v) != voice
+                && other
+                    .note
+                    .is_some_and(|note| note.pitch == pitch && note.channel == channel)
+        })
+    }
+}
+
+impl MidiHandler {
+    /// Closes the connection even if the final note offs couldn't be sent
+    pub fn close(mut self) -> Result<MidiInitialiser> {
+        let mut released = self.release_all();
+        if self.routing == VoiceRouting::Mpe {
+            released = released.and(self.send_mpe_configuration(0));
+        }
+        let midi_init = MidiInitialiser::from_output(self.sink.close());
+        released.map(|_| midi_init)
+    }
+}
+
+/// 14 bit pitch bend for `semitones` within `range` either way
+fn bend_value(semitones: f32, range: u8) -> u16 {
+    let offset = semitones / range as f32 * PITCH_BEND_CENTER as f32;
+    (PITCH_BEND_CENTER as f32 + offset)
+        .round()
+        .clamp(0.0, 0x3fff as f32) as u16
+}
+
+fn unit_to_7bit(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 127.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mpe_handler() -> MidiHandler<Vec<Vec<u8>>> {
+        let mut midi_h = MidiHandler::new(Vec::new());
+        midi_h.set_routing(VoiceRouting::Mpe).unwrap();
+        midi_h.sink.clear();
+        midi_h
+    }
+
+    fn note_on_channels(sent: &[Vec<u8>]) -> Vec<u8> {
+        sent.iter()
+            .filter(|msg| msg[0] & 0xf0 == NOTE_ON_MSG)
+            .map(|msg| msg[0] & 0x0f)
+            .collect()
+    }
+
+    #[test]
+    fn mpe_voices_get_their_own_member_channels() {
+        let mut midi_h = mpe_handler();
+        for voice in 0..(MIDI_CHANNELS - 1) as usize {
+            midi_h.play_voice(voice, 60 + voice as Pitch).unwrap();
+        }
+        assert_eq!(
+            note_on_channels(&midi_h.sink),
+            (1..MIDI_CHANNELS).collect::<Vec<u8>>()
+        );
+        assert!(midi_h.free_channels.is_empty());
+    }
+
+    #[test]
+    fn mpe_doubles_up_once_member_channels_run_out() {
+        let mut midi_h = mpe_handler();
+        for voice in 0..MIDI_CHANNELS as usize {
+            midi_h.play_voice(voice, 40 + voice as Pitch).unwrap();
+        }
+        // the 16th voice shares a channel by voice number
+        assert_eq!(note_on_channels(&midi_h.sink).last(), Some(&1));
+        // the channel stays taken while the other voice on it sounds
+        midi_h.release_voice(15).unwrap();
+        assert!(midi_h.free_channels.is_empty());
+        midi_h.release_voice(0).unwrap();
+        assert_eq!(midi_h.free_channels, [1]);
+    }
+
+    #[test]
+    fn released_mpe_channels_are_reused_least_recent_first() {
+        let mut midi_h = mpe_handler();
+        for voice in 0..(MIDI_CHANNELS - 1) as usize {
+            midi_h.play_voice(voice, 60 + voice as Pitch).unwrap();
+        }
+        midi_h.release_voice(4).unwrap();
+        midi_h.release_voice(2).unwrap();
+        // released twice doesn't free the channel twice
+        midi_h.release_voice(2).unwrap();
+        assert_eq!(midi_h.free_channels, [5, 3]);
+        midi_h.sink.clear();
+        midi_h.play_voice(2, 90).unwrap();
+        assert_eq!(note_on_channels(&midi_h.sink), [5]);
+    }
+
+    #[test]
+    fn release_all_frees_every_member_channel() {
+        let mut midi_h = mpe_handler();
+        for voice in 0..MIDI_CHANNELS as usize {
+            midi_h.play_voice(voice, 40 + voice as Pitch).unwrap();
+        }
+        midi_h.release_all().unwrap();
+        let mut free: Vec<u8> = midi_h.free_channels.iter().copied().collect();
+        free.sort_unstable();
+        assert_eq!(free, (1..MIDI_CHANNELS).collect::<Vec<u8>>());
     }
 }
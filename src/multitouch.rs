@@ -0,0 +1,149 @@
+use std::os::fd::AsRawFd;
+
+use evdev::{AbsoluteAxisType, Device, InputEventKind, Synchronization};
+use libc::{F_SETFL, O_NONBLOCK};
+
+use crate::{
+    error::{Result, TheraminError},
+    input::{BarPos, InputHandler, MappingMode, Smoothing},
+    midi::Expression,
+};
+
+/// Positions are left for the worker to realise with its tuning
+pub enum VoiceEvent {
+    Play(usize, BarPos),
+    Release(usize),
+    Express(usize, BarPos, Expression),
+}
+
+/// Reads a touchpad through evdev so each multitouch slot can be its own voice
+pub struct Touchpad {
+    dev: Device,
+    x_range: (i32, i32),
+    y_range: (i32, i32),
+    pressure_range: (i32, i32),
+    slot: usize,
+    contacts: Vec<InputHandler>,
+}
+
+impl Touchpad {
+    /// Opens the multitouch device with the same name ManyMouse reported
+    pub fn open(name: &str, note_width: u16, slot_widths: &[u8]) -> Result<Self> {
+        let dev = evdev::enumerate()
+            .map(|(_, dev)| dev)
+            .find(|dev| {
+                dev.name() == Some(name)
+                    && dev
+                        .supported_absolute_axes()
+                        .is_some_and(|axes| axes.contains(AbsoluteAxisType::ABS_MT_SLOT))
+            })
+            .ok_or_else(|| TheraminError::NoMultitouch(name.to_string()))?;
+        if unsafe { libc::fcntl(dev.as_raw_fd(), F_SETFL, O_NONBLOCK) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let abs_state = dev.get_abs_state()?;
+        let slot_info = abs_state[AbsoluteAxisType::ABS_MT_SLOT.0 as usize];
+        let x_info = abs_state[AbsoluteAxisType::ABS_MT_POSITION_X.0 as usize];
+        let y_info = abs_state[AbsoluteAxisType::ABS_MT_POSITION_Y.0 as usize];
+        let pressure_info = abs_state[AbsoluteAxisType::ABS_MT_PRESSURE.0 as usize];
+        let num_contacts = (slot_info.maximum + 1).max(1) as usize;
+        Ok(Touchpad {
+            dev,
+            x_range: (x_info.minimum, x_info.maximum),
+            y_range: (y_info.minimum, y_info.maximum),
+            pressure_range: (pressure_info.minimum, pressure_info.maximum),
+            slot: (slot_info.value.max(0) as usize).min(num_contacts - 1),
+            contacts: (0..num_contacts)
+                .map(|_| InputHandler::new(note_width, slot_widths, MappingMode::Absolute))
+                .collect(),
+        })
+    }
+
+    /// Notes only change at the end of a frame, see `InputHandler::queue_press`. Call this
+    /// every loop so smoothing catches up even while the pad sends nothing
+    pub fn poll(&mut self) -> Result<Vec<VoiceEvent>> {
+        let evs: Vec<_> = match self.dev.fetch_events() {
+            Ok(ev_iter) => ev_iter.collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut voice_evs = Vec::new();
+        for ev in evs {
+            match ev.kind() {
+                InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_SLOT) => {
+                    self.slot = (ev.value().max(0) as usize).min(self.contacts.len() - 1);
+                }
+                InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_TRACKING_ID) => {
+                    self.contacts[self.slot].queue_press(ev.value() >= 0);
+                }
+                InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_POSITION_X) => {
+                    let (min, max) = self.x_range;
+                    self.contacts[self.slot].handle_abs_move(ev.value(), min, max);
+                }
+                InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_POSITION_Y) => {
+                    let (min, max) = self.y_range;
+                    self.contacts[self.slot].handle_abs_y(ev.value(), min, max);
+                }
+                InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_PRESSURE) => {
+                    let (min, max) = self.pressure_range;
+                    self.contacts[self.slot].handle_pressure(ev.value(), min, max);
+                }
+                InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                    for (voice, contact) in self.contacts.iter_mut().enumerate() {
+                        contact.tick();
+                        let pos = contact.bar_pos();
+                        voice_evs.push(VoiceEvent::Express(voice, pos, contact.expression()));
+                        for pressed in contact.take_pending_presses() {
+                            contact.playing = pressed;
+                            if pressed {
+                                voice_evs.push(VoiceEvent::Play(voice, pos));
+                            } else {
+                                voice_evs.push(VoiceEvent::Release(voice));
+                            }
+                        }
+                        if contact.playing {
+                            voice_evs.push(VoiceEvent::Play(voice, pos));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        // a finger that's stopped sends nothing, but its smoothed position is still moving
+        for (voice, contact) in self.contacts.iter_mut().enumerate() {
+            if contact.playing && contact.tick() {
+                let pos = contact.bar_pos();
+                voice_evs.push(VoiceEvent::Express(voice, pos, contact.expression()));
+                voice_evs.push(VoiceEvent::Play(voice, pos));
+            }
+        }
+        Ok(voice_evs)
+    }
+
+    pub fn set_slot_widths(&mut self, slot_widths: &[u8]) {
+        for contact in self.contacts.iter_mut() {
+            contact.set_slot_widths(slot_widths);
+        }
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis: f32) {
+        for contact in self.contacts.iter_mut() {
+            contact.hysteresis = hysteresis;
+        }
+    }
+
+    /// Fingers catch up on every `poll`, whether or not the pad sent anything
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        for contact in self.contacts.iter_mut() {
+            contact.set_smoothing(smoothing);
+        }
+    }
+
+    /// Position of the lowest numbered finger on the pad
+    pub fn float_pos(&self) -> Option<f32> {
+        self.contacts
+            .iter()
+            .find(|contact| contact.playing)
+            .map(InputHandler::float_pos)
+    }
+}
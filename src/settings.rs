@@ -0,0 +1,55 @@
+use crate::{
+    arpeggiator::Arp,
+    clock::Grid,
+    harmony::{ChordMode, Harmony},
+    input::{
+        ButtonBindings, EdgeMode, HoldMode, MappingMode, MergePolicy, NoteWidths, ResponseCurve,
+        ScrollAction, Sensitivity, Smoothing,
+    },
+    keys::FollowKeys,
+    midi::{Controller, VoiceRouting},
+    tuning::{NoteRange, TuningOutput},
+};
+
+/// Per device options chosen in the GUI, kept while the device is deselected
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DevSettings {
+    pub mapping: MappingMode,
+    /// Read the device as a multitouch touchpad with a voice per finger
+    pub polyphonic: bool,
+    pub voice_routing: VoiceRouting,
+    /// Also write MIDI 2.0 to `ump::ump_file_path`
+    pub ump_file: bool,
+    /// Controller driven by the Y axis
+    pub y_controller: Option<Controller>,
+    pub tuning_output: TuningOutput,
+    /// Overlap notes instead of retriggering on every boundary
+    pub legato: bool,
+    /// Glide time sent as CC5, `None` leaves portamento off
+    pub portamento: Option<u8>,
+    /// How far past a slot's edge the pointer must go to change note, as a fraction of a
+    /// slot's width up to a half
+    pub hysteresis: f32,
+    pub smoothing: Smoothing,
+    /// Only used with relative mapping
+    pub curve: ResponseCurve,
+    /// Only used with relative mapping
+    pub sensitivity: Sensitivity,
+    /// Keys on the bar, out of the ones the tuning has
+    pub range: NoteRange,
+    /// Only used with relative mapping
+    pub edge: EdgeMode,
+    pub note_widths: NoteWidths,
+    /// Settles presses from devices merged into this one
+    pub merge_policy: MergePolicy,
+    pub buttons: ButtonBindings,
+    pub scroll: ScrollAction,
+    pub hold: HoldMode,
+    pub chord: ChordMode,
+    pub harmony: Harmony,
+    /// Arpeggiates the chord, or a run up the scale, while the note's held
+    pub arp: Option<Arp>,
+    /// Holds new notes back to the MIDI clock's next grid line while it's running
+    pub quantize: Option<Grid>,
+    pub follow_keys: FollowKeys,
+}
@@ -0,0 +1,611 @@
+use std::{fs, path::Path};
+
+use crate::{
+    error::{Result, TheraminError},
+    input::BarPos,
+    midi::{Pitch, HIGHEST_MIDI_NOTE},
+};
+
+const A4_KEY: u8 = 69;
+const A4_FREQ: f64 = 440.0;
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+const NON_REALTIME: u8 = 0x7E;
+const REALTIME: u8 = 0x7F;
+const ALL_DEVICES: u8 = 0x7F;
+const MIDI_TUNING: u8 = 0x08;
+const BULK_DUMP_REPLY: u8 = 0x01;
+const SINGLE_NOTE_TUNING_CHANGE: u8 = 0x02;
+const MTS_NAME_LEN: usize = 16;
+/// Most keys a single note tuning change can carry, the count is one data byte
+const MAX_NOTE_CHANGES: usize = 127;
+/// The highest frequency MTS can express packed into 21 bits, one step below the reserved
+/// 7F 7F 7F "no change" value
+const MTS_MAX: u32 = 0x1FFFFE;
+/// The tuning program theramin retunes
+const MTS_PROGRAM: u8 = 0;
+
+/// How pitches between the 12 tone notes reach the synth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TuningOutput {
+    /// Each slot plays its nearest MIDI note bent to the exact pitch
+    #[default]
+    PitchBend,
+    /// Each slot plays its own key and the synth is retuned with MIDI Tuning Standard SysEx
+    Mts,
+}
+
+/// A Scala .scl scale, degree 0 is the implicit 0 cents and the last degree is the period
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    pub cents: Vec<f64>,
+}
+
+impl Default for Scale {
+    /// 12 tone equal temperament, for a keyboard map loaded without a scale
+    fn default() -> Self {
+        Scale {
+            description: "12 tone equal temperament".to_string(),
+            cents: (1..=12).map(|step| step as f64 * 100.0).collect(),
+        }
+    }
+}
+
+impl Scale {
+    pub fn load(path: &Path) -> Result<Self> {
+        Scale::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = scala_lines(text);
+        let description = lines.next().unwrap_or_default().to_string();
+        let count: usize = parse_first(lines.next(), "scale", "note count")?;
+        let cents = lines
+            .take(count)
+            .map(parse_pitch)
+            .collect::<Result<Vec<f64>>>()?;
+        if cents.len() != count || count == 0 {
+            return Err(TheraminError::Tuning(format!(
+                "scale lists {} notes but has {}",
+                count,
+                cents.len()
+            )));
+        }
+        Ok(Scale { description, cents })
+    }
+
+    /// Just the notes of `keys` in 12 tone, with degree 0 on the lowest and repeating every
+    /// octave
+    pub fn from_keys(keys: &[Pitch]) -> Self {
+        let lowest = keys.iter().min().copied().unwrap_or_default();
+        let mut steps: Vec<u8> = keys
+            .iter()
+            .map(|key| (key - lowest) % 12)
+            .filter(|step| *step != 0)
+            .collect();
+        steps.sort_unstable();
+        steps.dedup();
+        Scale {
+            description: "Keys held".to_string(),
+            cents: steps
+                .into_iter()
+                .map(|step| step as f64 * 100.0)
+                .chain([1200.0])
+                .collect(),
+        }
+    }
+
+    pub fn period(&self) -> f64 {
+        *self.cents.last().unwrap()
+    }
+
+    /// Cents of any degree, going past the period or below 0 wraps into other periods
+    pub fn degree_cents(&self, degree: i32) -> f64 {
+        let len = self.cents.len() as i32;
+        let (periods, step) = (degree.div_euclid(len), degree.rem_euclid(len));
+        let step_cents = if step == 0 {
+            0.0
+        } else {
+            self.cents[step as usize - 1]
+        };
+        periods as f64 * self.period() + step_cents
+    }
+}
+
+/// A Scala .kbm keyboard mapping
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMap {
+    pub first_key: u8,
+    pub last_key: u8,
+    /// The key degree 0 is on
+    pub middle_key: u8,
+    pub reference_key: u8,
+    pub reference_freq: f64,
+    /// Degree the mapping repeats at, 0 means the scale's period
+    pub octave_degree: usize,
+    /// Degree for each key in a repeat, `None` for unmapped keys, empty for a linear mapping
+    pub mapping: Vec<Option<usize>>,
+}
+
+impl Default for KeyboardMap {
+    /// Linear mapping with degree 0 on middle C and A above it at 440Hz
+    fn default() -> Self {
+        KeyboardMap {
+            first_key: 0,
+            last_key: HIGHEST_MIDI_NOTE,
+            middle_key: 60,
+            reference_key: A4_KEY,
+            reference_freq: A4_FREQ,
+            octave_degree: 0,
+            mapping: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        KeyboardMap::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = scala_lines(text);
+        let size: usize = parse_first(lines.next(), "keyboard map", "map size")?;
+        let first_key: u8 = parse_first(lines.next(), "keyboard map", "first key")?;
+        let last_key: u8 = parse_first(lines.next(), "keyboard map", "last key")?;
+        let middle_key: u8 = parse_first(lines.next(), "keyboard map", "middle key")?;
+        let reference_key: u8 = parse_first(lines.next(), "keyboard map", "reference key")?;
+        let reference_freq: f64 = parse_first(lines.next(), "keyboard map", "reference frequency")?;
+        let octave_degree: usize = parse_first(lines.next(), "keyboard map", "octave degree")?;
+        let mapping = lines
+            .take(size)
+            .map(|line| match line.split_whitespace().next() {
+                Some("x") | None => Ok(None),
+                Some(degree) => degree.parse().map(Some).map_err(|_| {
+                    TheraminError::Tuning(format!("bad keyboard map degree {}", degree))
+                }),
+            })
+            .collect::<Result<Vec<Option<usize>>>>()?;
+        // a short map leaves the remaining keys unmapped
+        let mapping = if size == 0 {
+            mapping
+        } else {
+            let mut mapping = mapping;
+            mapping.resize(size, None);
+            mapping
+        };
+        Ok(KeyboardMap {
+            first_key: first_key.min(HIGHEST_MIDI_NOTE),
+            last_key: last_key.min(HIGHEST_MIDI_NOTE),
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree,
+            mapping,
+        })
+    }
+
+    /// Moves degree 0 to the nearest key with the same note name as `key`, sounding at its 12
+    /// tone pitch
+    pub fn rooted_on(&self, key: Pitch) -> Self {
+        let shift = (key as i32 - self.middle_key as i32 + 6).rem_euclid(12) - 6;
+        let root = (self.middle_key as i32 + shift).clamp(0, HIGHEST_MIDI_NOTE as i32) as u8;
+        KeyboardMap {
+            middle_key: root,
+            reference_key: root,
+            reference_freq: A4_FREQ * 2f64.powf((root as f64 - A4_KEY as f64) / 12.0),
+            ..self.clone()
+        }
+    }
+
+    /// Scale degree counted from `middle_key`, `None` if the key is unmapped
+    fn key_degree(&self, key: u8, scale: &Scale) -> Option<i32> {
+        if key < self.first_key || key > self.last_key {
+            return None;
+        }
+        let offset = key as i32 - self.middle_key as i32;
+        if self.mapping.is_empty() {
+            return Some(offset);
+        }
+        let size = self.mapping.len() as i32;
+        let repeat_degree = match self.octave_degree {
+            0 => scale.cents.len(),
+            degree => degree,
+        } as i32;
+        let degree = self.mapping[offset.rem_euclid(size) as usize]?;
+        Some(offset.div_euclid(size) * repeat_degree + degree as i32)
+    }
+}
+
+/// The keys a theremin's bar covers, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteRange {
+    pub lowest: Pitch,
+    pub highest: Pitch,
+}
+
+impl Default for NoteRange {
+    fn default() -> Self {
+        NoteRange {
+            lowest: 0,
+            highest: HIGHEST_MIDI_NOTE,
+        }
+    }
+}
+
+/// One place on the bar
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub key: Pitch,
+    /// Fractional MIDI note, 69.0 is A440
+    pub pitch: f32,
+    /// Degree within the period and its cents, `None` in plain 12 tone
+    pub degree: Option<(usize, f32)>,
+}
+
+/// Which keys are on the bar and what pitch each one sounds at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub name: Option<String>,
+    slots: Vec<Slot>,
+}
+
+impl Default for Tuning {
+    /// 12 tone equal temperament over every MIDI note
+    fn default() -> Self {
+        Tuning {
+            name: None,
+            slots: (0..=HIGHEST_MIDI_NOTE)
+                .map(|key| Slot {
+                    key,
+                    pitch: key as f32,
+                    degree: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Tuning {
+    /// Unmapped keys and keys that would sound outside the MIDI range are left off the bar
+    pub fn new(scale: &Scale, kbm: &KeyboardMap) -> Result<Self> {
+        let reference_degree = kbm.key_degree(kbm.reference_key, scale).unwrap_or(
+            // an unmapped reference key is taken as if the mapping were linear
+            kbm.reference_key as i32 - kbm.middle_key as i32,
+        );
+        let reference_cents = scale.degree_cents(reference_degree);
+        let reference_pitch = A4_KEY as f64 + 12.0 * (kbm.reference_freq / A4_FREQ).log2();
+        let slots: Vec<Slot> = (0..=HIGHEST_MIDI_NOTE)
+            .filter_map(|key| {
+                let degree = kbm.key_degree(key, scale)?;
+                let cents = scale.degree_cents(degree);
+                let pitch = reference_pitch + (cents - reference_cents) / 100.0;
+                let in_period = degree.rem_euclid(scale.cents.len() as i32);
+                (0.0..=HIGHEST_MIDI_NOTE as f64)
+                    .contains(&pitch.round())
+                    .then_some(Slot {
+                        key,
+                        pitch: pitch as f32,
+                        degree: Some((in_period as usize, scale.degree_cents(in_period) as f32)),
+                    })
+            })
+            .collect();
+        if slots.is_empty() {
+            return Err(TheraminError::Tuning(
+                "no keys of the keyboard map are playable".to_string(),
+            ));
+        }
+        Ok(Tuning {
+            name: Some(scale.description.clone()),
+            slots,
+        })
+    }
+
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// Different degrees on the bar, so a keyboard map leaving keys out counts for less than
+    /// the whole scale
+    pub fn slots_per_period(&self) -> usize {
+        let mut degrees: Vec<usize> = self
+            .slots
+            .iter()
+            .filter_map(|slot| slot.degree.map(|(degree, _)| degree))
+            .collect();
+        degrees.sort_unstable();
+        degrees.dedup();
+        match degrees.len() {
+            // plain 12 tone
+            0 => 12,
+            len => len,
+        }
+    }
+
+    /// Just the slots whose keys are in `range`, or the whole tuning if none of them are
+    pub fn in_range(&self, range: NoteRange) -> Tuning {
+        let slots: Vec<Slot> = self
+            .slots
+            .iter()
+            .filter(|slot| (range.lowest..=range.highest).contains(&slot.key))
+            .cloned()
+            .collect();
+        if slots.is_empty() {
+            return self.clone();
+        }
+        Tuning {
+            name: self.name.clone(),
+            slots,
+        }
+    }
+
+    /// The note to play for `bar_pos`'s slot and the bend from it. With `glide` the pitch
+    /// slides between slot centres, otherwise it's fixed across each slot
+    pub fn realise(&self, bar_pos: BarPos, glide: bool, output: TuningOutput) -> (Pitch, f32) {
+        let slot = &self.slots[bar_pos.slot.min(self.slots.len() - 1)];
+        let pitch = if glide {
+            self.pitch_at(bar_pos.pos - 0.5)
+        } else {
+            slot.pitch
+        };
+        match output {
+            TuningOutput::PitchBend => {
+                let note = slot.pitch.round();
+                (note as Pitch, pitch - note)
+            }
+            // the synth already sounds the key at the slot's pitch
+            TuningOutput::Mts => (slot.key, pitch - slot.pitch),
+        }
+    }
+
+    /// Pitch every key should sound at, keys off the bar stay in 12 tone
+    fn key_pitches(&self) -> Vec<f32> {
+        let mut pitches: Vec<f32> = (0..=HIGHEST_MIDI_NOTE).map(|key| key as f32).collect();
+        for slot in self.slots.iter() {
+            pitches[slot.key as usize] = slot.pitch;
+        }
+        pitches
+    }
+
+    /// MTS bulk tuning dump of every key, the synth applies it to notes started afterwards
+    pub fn mts_bulk_dump(&self) -> Vec<u8> {
+        let name = self.name.as_deref().unwrap_or("12 tone equal");
+        let mut name: Vec<u8> = name
+            .bytes()
+            .map(|b| {
+                if b.is_ascii() && !b.is_ascii_control() {
+                    b
+                } else {
+                    b'?'
+                }
+            })
+            .take(MTS_NAME_LEN)
+            .collect();
+        name.resize(MTS_NAME_LEN, b' ');
+        let mut body = vec![
+            NON_REALTIME,
+            ALL_DEVICES,
+            MIDI_TUNING,
+            BULK_DUMP_REPLY,
+            MTS_PROGRAM,
+        ];
+        body.extend(name);
+        body.extend(self.key_pitches().into_iter().flat_map(mts_frequency));
+        let checksum = body.iter().fold(0, |sum, b| sum ^ b) & 0x7f;
+        let mut msg = vec![SYSEX_START];
+        msg.extend(body);
+        msg.extend([checksum, SYSEX_END]);
+        msg
+    }
+
+    /// Real time single note tuning changes for every key, these also retune sounding notes
+    pub fn mts_note_changes(&self) -> Vec<Vec<u8>> {
+        let pitches = self.key_pitches();
+        pitches
+            .chunks(MAX_NOTE_CHANGES)
+            .enumerate()
+            .map(|(chunk, pitches)| {
+                let mut msg = vec![
+                    SYSEX_START,
+                    REALTIME,
+                    ALL_DEVICES,
+                    MIDI_TUNING,
+                    SINGLE_NOTE_TUNING_CHANGE,
+                    MTS_PROGRAM,
+                    pitches.len() as u8,
+                ];
+                for (i, pitch) in pitches.iter().enumerate() {
+                    msg.push((chunk * MAX_NOTE_CHANGES + i) as u8);
+                    msg.extend(mts_frequency(*pitch));
+                }
+                msg.push(SYSEX_END);
+                msg
+            })
+            .collect()
+    }
+
+    /// Pitch between slot centres, held flat past the ends of the bar
+    fn pitch_at(&self, pos: f32) -> f32 {
+        let last = self.slots.len() - 1;
+        let pos = pos.clamp(0.0, last as f32);
+        let below = pos as usize;
+        let above = (below + 1).min(last);
+        let frac = pos - below as f32;
+        self.slots[below].pitch + (self.slots[above].pitch - self.slots[below].pitch) * frac
+    }
+}
+
+/// Semitone then the fraction of it in 14 bits, MTS's 3 byte frequency
+fn mts_frequency(pitch: f32) -> [u8; 3] {
+    let note = pitch.clamp(0.0, HIGHEST_MIDI_NOTE as f32).floor();
+    let frac = ((pitch - note).clamp(0.0, 1.0) * 0x4000 as f32).round() as u32;
+    let value = ((note as u32) << 14 | frac.min(0x3fff)).min(MTS_MAX);
+    [
+        (value >> 14) as u8,
+        (value >> 7) as u8 & 0x7f,
+        value as u8 & 0x7f,
+    ]
+}
+
+/// Lines of a Scala file that aren't comments
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('!'))
+}
+
+fn parse_first<T: std::str::FromStr>(line: Option<&str>, file: &str, field: &str) -> Result<T> {
+    line.and_then(|line| line.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| TheraminError::Tuning(format!("{} is missing its {}", file, field)))
+}
+
+/// A scale pitch in cents, given as cents if it has a '.' and as a ratio or integer otherwise
+fn parse_pitch(line: &str) -> Result<f64> {
+    let value = line.split_whitespace().next().unwrap_or_default();
+    let bad_pitch = || TheraminError::Tuning(format!("bad scale pitch {}", value));
+    if value.contains('.') {
+        return value.parse().map_err(|_| bad_pitch());
+    }
+    let (num, den) = value.split_once('/').unwrap_or((value, "1"));
+    let num: f64 = num.parse().map_err(|_| bad_pitch())?;
+    let den: f64 = den.parse().map_err(|_| bad_pitch())?;
+    if num <= 0.0 || den <= 0.0 {
+        return Err(bad_pitch());
+    }
+    Ok(1200.0 * (num / den).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PENTATONIC_SCL: &str = "! pentatonic.scl
+!
+Pentatonic with a fifth
+ 5
+  ! comments can be indented
+ 200.0
+ 400.0
+ 3/2
+ 900.0
+ 2/1
+";
+
+    const GAPPED_KBM: &str = "! gapped.kbm
+ 12
+ 0
+ 127
+ 60
+ 69
+ 440.0
+ 5
+! a degree per white key
+ 0
+ x
+ 1
+ x
+ 2
+ x
+ x
+ 3
+ x
+ 4
+ x
+ x
+";
+
+    #[test]
+    fn parses_scale_with_indented_comments() {
+        let scale = Scale::parse(PENTATONIC_SCL).unwrap();
+        assert_eq!(scale.description, "Pentatonic with a fifth");
+        assert_eq!(scale.cents.len(), 5);
+        assert_eq!(scale.cents[..2], [200.0, 400.0]);
+        assert!((scale.cents[2] - 701.955).abs() < 0.001);
+        assert_eq!(scale.period(), 1200.0);
+    }
+
+    #[test]
+    fn scale_with_too_few_notes_is_an_error() {
+        assert!(Scale::parse("short\n 3\n 100.0\n 2/1\n").is_err());
+    }
+
+    #[test]
+    fn parses_keyboard_map() {
+        let kbm = KeyboardMap::parse(GAPPED_KBM).unwrap();
+        assert_eq!(kbm.middle_key, 60);
+        assert_eq!(kbm.reference_freq, 440.0);
+        assert_eq!(kbm.mapping.len(), 12);
+        let scale = Scale::parse(PENTATONIC_SCL).unwrap();
+        assert_eq!(kbm.key_degree(60, &scale), Some(0));
+        assert_eq!(kbm.key_degree(61, &scale), None);
+        assert_eq!(kbm.key_degree(67, &scale), Some(3));
+        // the map repeats every 12 keys and 5 degrees
+        assert_eq!(kbm.key_degree(72, &scale), Some(5));
+        assert_eq!(kbm.key_degree(48, &scale), Some(-5));
+    }
+
+    #[test]
+    fn unmapped_keys_are_left_off_the_bar() {
+        let scale = Scale::parse(PENTATONIC_SCL).unwrap();
+        let kbm = KeyboardMap::parse(GAPPED_KBM).unwrap();
+        let tuning = Tuning::new(&scale, &kbm).unwrap();
+        assert!(tuning.slots().iter().all(|slot| slot.key % 12 != 1));
+        let a4 = tuning.slots().iter().find(|slot| slot.key == 69).unwrap();
+        assert_eq!(a4.pitch, 69.0);
+    }
+
+    #[test]
+    fn bulk_dump_of_default_tuning() {
+        let msg = Tuning::default().mts_bulk_dump();
+        assert_eq!(msg.len(), 408);
+        assert_eq!(msg[..6], [0xF0, 0x7E, 0x7F, 0x08, 0x01, 0x00]);
+        assert_eq!(&msg[6..22], b"12 tone equal   ");
+        assert_eq!(msg[22..25], [0, 0, 0]);
+        assert_eq!(msg[22 + 60 * 3..22 + 61 * 3], [60, 0, 0]);
+        assert_eq!(msg[406..], [0x57, 0xF7]);
+    }
+
+    #[test]
+    fn note_changes_split_at_127_keys() {
+        let msgs = Tuning::default().mts_note_changes();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0][..7], [0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 127]);
+        assert_eq!(msgs[0][7..11], [0, 0, 0, 0]);
+        assert_eq!(msgs[0].len(), 7 + 127 * 4 + 1);
+        assert_eq!(
+            msgs[1],
+            [0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 1, 127, 127, 0, 0, 0xF7]
+        );
+    }
+
+    #[test]
+    fn mts_frequency_bytes() {
+        assert_eq!(mts_frequency(69.0), [69, 0, 0]);
+        assert_eq!(mts_frequency(69.5), [69, 0x40, 0]);
+        assert_eq!(mts_frequency(-3.0), [0, 0, 0]);
+        // just under the top would round into the reserved "no change" value
+        assert_eq!(mts_frequency(127.99999), [0x7F, 0x7F, 0x7E]);
+    }
+
+    #[test]
+    fn twelve_tone_scale_matches_default_tuning() {
+        let tuning = Tuning::new(&Scale::default(), &KeyboardMap::default()).unwrap();
+        for (slot, default) in tuning.slots().iter().zip(Tuning::default().slots()) {
+            assert_eq!(slot.key, default.key);
+            assert!((slot.pitch - default.pitch).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn slots_per_period_counts_degrees_on_the_bar() {
+        assert_eq!(Tuning::default().slots_per_period(), 12);
+        let scale = Scale::parse(PENTATONIC_SCL).unwrap();
+        let tuning = Tuning::new(&scale, &KeyboardMap::default()).unwrap();
+        assert_eq!(tuning.slots_per_period(), 5);
+        let kbm = KeyboardMap::parse(GAPPED_KBM).unwrap();
+        let tuning = Tuning::new(&scale, &kbm).unwrap();
+        assert_eq!(tuning.slots_per_period(), 5);
+    }
+}
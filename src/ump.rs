@@ -0,0 +1,351 @@
+use std::{io::Write, path::PathBuf};
+
+use crate::{
+    error::Result,
+    midi::{Expression, Pitch},
+};
+
+const MIDI2_CHANNEL_VOICE: u32 = 0x4;
+const REGISTERED_PER_NOTE_CONTROLLER: u32 = 0x0;
+const NOTE_OFF: u32 = 0x8;
+const NOTE_ON: u32 = 0x9;
+const POLY_PRESSURE: u32 = 0xA;
+const CONTROL_CHANGE: u32 = 0xB;
+
+const PITCH_7_9_ATTRIBUTE: u32 = 0x3;
+const PITCH_7_25_CONTROLLER: u32 = 0x3;
+const TIMBRE_CC: u32 = 74;
+const VELOCITY: u16 = 0xffff;
+
+/// Somewhere to put Universal MIDI Packets while OS transports don't take them
+pub trait UmpSink {
+    fn write_packet(&mut self, words: &[u32]) -> Result<()>;
+}
+
+/// Keeps every word, for tests or inspecting output
+impl UmpSink for Vec<u32> {
+    fn write_packet(&mut self, words: &[u32]) -> Result<()> {
+        self.extend_from_slice(words);
+        Ok(())
+    }
+}
+
+/// Writes words big endian, the byte order used by UMP files and the network transport
+pub struct UmpWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> UmpWriter<W> {
+    pub fn new(out: W) -> Self {
+        UmpWriter { out }
+    }
+}
+
+impl<W: Write> UmpSink for UmpWriter<W> {
+    fn write_packet(&mut self, words: &[u32]) -> Result<()> {
+        for word in words {
+            self.out.write_all(&word.to_be_bytes())?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Builds MIDI 2.0 channel voice messages for one group and channel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UmpEncoder {
+    pub group: u8,
+    pub channel: u8,
+}
+
+impl UmpEncoder {
+    fn header(&self, status: u32, byte3: u8, byte4: u8) -> u32 {
+        MIDI2_CHANNEL_VOICE << 28
+            | (self.group as u32 & 0xf) << 24
+            | status << 20
+            | (self.channel as u32 & 0xf) << 16
+            | (byte3 as u32 & 0x7f) << 8
+            | byte4 as u32
+    }
+
+    /// Carries `pitch` as a Pitch 7.9 attribute so the note starts exactly there
+    pub fn note_on(&self, note: Pitch, velocity: u16, pitch: f32) -> [u32; 2] {
+        [
+            self.header(NOTE_ON, note, PITCH_7_9_ATTRIBUTE as u8),
+            (velocity as u32) << 16 | pitch_fixed(pitch, 9),
+        ]
+    }
+
+    pub fn note_off(&self, note: Pitch, velocity: u16) -> [u32; 2] {
+        [self.header(NOTE_OFF, note, 0), (velocity as u32) << 16]
+    }
+
+    /// Per note Pitch 7.25, the absolute pitch in semitones
+    pub fn per_note_pitch(&self, note: Pitch, pitch: f32) -> [u32; 2] {
+        [
+            self.header(
+                REGISTERED_PER_NOTE_CONTROLLER,
+                note,
+                PITCH_7_25_CONTROLLER as u8,
+            ),
+            pitch_fixed(pitch, 25),
+        ]
+    }
+
+    pub fn poly_pressure(&self, note: Pitch, value: u32) -> [u32; 2] {
+        [self.header(POLY_PRESSURE, note, 0), value]
+    }
+
+    pub fn control_change(&self, index: u8, value: u32) -> [u32; 2] {
+        [self.header(CONTROL_CHANGE, index, 0), value]
+    }
+}
+
+/// Semitones as 7 integer bits and `frac_bits` fractional bits
+fn pitch_fixed(pitch: f32, frac_bits: u32) -> u32 {
+    let max = (1u64 << (7 + frac_bits)) - 1;
+    ((pitch.max(0.0) as f64 * (1u64 << frac_bits) as f64) as u64).min(max) as u32
+}
+
+fn unit_to_32bit(value: f32) -> u32 {
+    (value.clamp(0.0, 1.0) as f64 * u32::MAX as f64) as u32
+}
+
+/// Mirrors `MidiHandler` in MIDI 2.0, where a note's exact pitch is `note + bend`
+pub struct UmpHandler<S: UmpSink> {
+    sink: S,
+    encoder: UmpEncoder,
+    voices: Vec<Option<Pitch>>,
+    expressions: Vec<Expression>,
+    /// For note ons, note offs keep the full velocity
+    velocity: u16,
+    /// Notes let go of while the pedal is down, `None` while it's up
+    pedal_notes: Option<Vec<Pitch>>,
+}
+
+impl<S: UmpSink> UmpHandler<S> {
+    pub fn new(sink: S) -> Self {
+        UmpHandler {
+            sink,
+            encoder: UmpEncoder::default(),
+            voices: Vec::new(),
+            expressions: Vec::new(),
+            velocity: VELOCITY,
+            pedal_notes: None,
+        }
+    }
+
+    /// Takes the 7 bit velocity `MidiHandler` uses
+    pub fn set_velocity(&mut self, velocity: u8) {
+        self.velocity = (velocity.min(0x7f) as u32 * VELOCITY as u32 / 0x7f) as u16;
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    fn ensure_voice(&mut self, voice: usize) {
+        if voice >= self.voices.len() {
+            self.voices.resize(voice + 1, None);
+            self.expressions.resize(voice + 1, Expression::default());
+        }
+    }
+
+    pub fn play_voice(&mut self, voice: usize, note: Pitch) -> Result<()> {
+        self.ensure_voice(voice);
+        match self.voices[voice] {
+            Some(current) if current == note => return Ok(()),
+            Some(_) => self.release_voice(voice)?,
+            None => (),
+        }
+        // struck again while the pedal's holding it, so it's ended rather than stacked
+        if let Some(pedal_notes) = self.pedal_notes.as_mut() {
+            let held = pedal_notes.len();
+            pedal_notes.retain(|&pedal_note| pedal_note != note);
+            if pedal_notes.len() != held {
+                self.stop_note(note)?;
+            }
+        }
+        let expression = self.expressions[voice];
+        let pitch = note as f32 + expression.bend;
+        self.sink
+            .write_packet(&self.encoder.note_on(note, self.velocity, pitch))?;
+        self.sink.write_packet(
+            &self
+                .encoder
+                .poly_pressure(note, unit_to_32bit(expression.pressure)),
+        )?;
+        self.voices[voice] = Some(note);
+        Ok(())
+    }
+
+    pub fn release_voice(&mut self, voice: usize) -> Result<()> {
+        let Some(note) = self.voices.get_mut(voice).and_then(Option::take) else {
+            return Ok(());
+        };
+        match self.pedal_notes.as_mut() {
+            Some(pedal_notes) => {
+                if !pedal_notes.contains(&note) {
+                    pedal_notes.push(note);
+                }
+                Ok(())
+            }
+            None => self.stop_note(note),
+        }
+    }
+
+    /// Sends the note off unless a voice is still holding it
+    fn stop_note(&mut self, note: Pitch) -> Result<()> {
+        if !self.voices.contains(&Some(note)) {
+            self.sink
+                .write_packet(&self.encoder.note_off(note, VELOCITY))?;
+        }
+        Ok(())
+    }
+
+    /// Notes held by the pedal too, though the pedal stays down
+    pub fn release_all(&mut self) -> Result<()> {
+        (0..self.voices.len()).try_for_each(|voice| self.release_voice(voice))?;
+        let pedal_notes = self.pedal_notes.as_mut().map(std::mem::take);
+        pedal_notes
+            .unwrap_or_default()
+            .into_iter()
+            .try_for_each(|note| self.stop_note(note))
+    }
+
+    /// Mirrors `MidiHandler::set_pedal`
+    pub fn set_pedal(&mut self, down: bool) -> Result<()> {
+        if down {
+            self.pedal_notes.get_or_insert_with(Vec::new);
+            return Ok(());
+        }
+        self.pedal_notes
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .try_for_each(|note| self.stop_note(note))
+    }
+
+    pub fn express_voice(&mut self, voice: usize, expression: Expression) -> Result<()> {
+        self.ensure_voice(voice);
+        let prev = std::mem::replace(&mut self.expressions[voice], expression);
+        let Some(note) = self.voices[voice] else {
+            return Ok(());
+        };
+        if prev.bend != expression.bend {
+            let pitch = note as f32 + expression.bend;
+            self.sink
+                .write_packet(&self.encoder.per_note_pitch(note, pitch))?;
+        }
+        if prev.pressure != expression.pressure {
+            self.sink.write_packet(
+                &self
+                    .encoder
+                    .poly_pressure(note, unit_to_32bit(expression.pressure)),
+            )?;
+        }
+        if prev.timbre != expression.timbre {
+            self.sink.write_packet(
+                &self
+                    .encoder
+                    .control_change(TIMBRE_CC as u8, unit_to_32bit(expression.timbre)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a device's UMP stream is written when file output is on
+pub fn ump_file_path(dev_name: &str) -> PathBuf {
+    let file_name: String = dev_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    std::env::temp_dir().join(format!("theramin-{}.ump", file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_on_carries_pitch_7_9_attribute() {
+        let encoder = UmpEncoder::default();
+        assert_eq!(
+            encoder.note_on(60, 0xffff, 60.5),
+            [0x4090_3C03, 0xFFFF_7900]
+        );
+    }
+
+    #[test]
+    fn note_off_has_group_and_channel() {
+        let encoder = UmpEncoder {
+            group: 1,
+            channel: 2,
+        };
+        assert_eq!(encoder.note_off(60, 0x8000), [0x4182_3C00, 0x8000_0000]);
+    }
+
+    #[test]
+    fn per_note_pitch_is_7_25() {
+        let encoder = UmpEncoder::default();
+        assert_eq!(
+            encoder.per_note_pitch(60, 60.25),
+            [0x4000_3C03, 0x7880_0000]
+        );
+    }
+
+    #[test]
+    fn pitch_clamps_to_its_bits() {
+        assert_eq!(pitch_fixed(-1.0, 9), 0);
+        assert_eq!(pitch_fixed(200.0, 9), 0xFFFF);
+    }
+
+    #[test]
+    fn handler_plays_bent_note_then_releases() {
+        let mut ump_h = UmpHandler::new(Vec::new());
+        let bent = Expression {
+            bend: 0.5,
+            ..Expression::default()
+        };
+        ump_h.express_voice(0, bent).unwrap();
+        ump_h.play_voice(0, 60).unwrap();
+        ump_h.release_voice(0).unwrap();
+        assert_eq!(
+            ump_h.sink(),
+            &[
+                0x4090_3C03,
+                0xFFFF_7900,
+                0x40A0_3C00,
+                0xFFFF_FFFF,
+                0x4080_3C00,
+                0xFFFF_0000
+            ]
+        );
+    }
+
+    #[test]
+    fn pedal_holds_note_off_until_lifted() {
+        let mut ump_h = UmpHandler::new(Vec::new());
+        ump_h.set_pedal(true).unwrap();
+        ump_h.play_voice(0, 60).unwrap();
+        ump_h.release_voice(0).unwrap();
+        assert_eq!(ump_h.sink().len(), 4);
+        ump_h.set_pedal(false).unwrap();
+        assert_eq!(ump_h.sink()[4..], [0x4080_3C00, 0xFFFF_0000]);
+    }
+
+    #[test]
+    fn restruck_pedal_note_is_ended_first() {
+        let mut ump_h = UmpHandler::new(Vec::new());
+        ump_h.set_pedal(true).unwrap();
+        ump_h.play_voice(0, 60).unwrap();
+        ump_h.release_voice(0).unwrap();
+        ump_h.play_voice(0, 60).unwrap();
+        assert_eq!(ump_h.sink()[4..6], [0x4080_3C00, 0xFFFF_0000]);
+        assert_eq!(ump_h.sink()[6], 0x4090_3C03);
+        // lifting the pedal leaves the struck note alone
+        ump_h.set_pedal(false).unwrap();
+        assert_eq!(ump_h.sink().len(), 10);
+    }
+}
@@ -1,29 +1,96 @@
+use std::{fs::File, path::PathBuf, time::Instant};
+
 use dioxus::prelude::*;
 use tokio::sync::{mpsc, watch};
 
 use crate::{
-    input::InputHandler,
-    manymouse::{self, Axis, Button, ManyMouse},
-    midi::{MidiHandler, MidiInitialiser},
+    arpeggiator::{Arp, ArpTempo, Arpeggiator, ARP_SCALE_NOTES},
+    clock::{ClockPos, MidiClock},
+    error::{Result, TheraminError},
+    harmony::{ChordMode, ChordTone, CHORD_EXTRA_NOTES, MAX_CHORD_NOTES},
+    input::{
+        BarPos, ButtonAction, HoldMode, InputHandler, MappingMode, MergePolicy, NoteWidths,
+        ScrollAction, Sensitivity,
+    },
+    keys::{FollowKeys, KeysIn},
+    learn::{ControlEvent, ControlIn, LearnSource, LearnTarget},
+    manymouse::{self, Axis, ManyMouse},
+    midi::{
+        Controller, Expression, MidiHandler, MidiInitialiser, Pitch, VoiceRouting,
+        HIGHEST_MIDI_NOTE, VEL,
+    },
+    settings::DevSettings,
+    tuning::{KeyboardMap, NoteRange, Scale, Tuning, TuningOutput},
+    ump::{ump_file_path, UmpHandler, UmpWriter},
 };
 
+#[cfg(target_os = "linux")]
+use crate::multitouch::{Touchpad, VoiceEvent};
+
 const MSG_BUFF_SIZE: usize = 30;
+/// Oldest errors are dropped past this
+const MAX_LOGGED_ERRORS: usize = 20;
+/// Scales each device keeps to step through
+const MAX_RECENT_SCALES: usize = 8;
+pub const CLOCK_PORT_NAME: &str = "Theramin clock in";
+pub const KEYS_PORT_NAME: &str = "Theramin keys in";
+pub const CONTROL_PORT_NAME: &str = "Theramin control in";
 const DEFAULT_NOTE_WIDTH: u16 = 200;
+const OCTAVE: i8 = 12;
+/// Semitones either way the octave buttons and scroll wheel can go
+const MAX_TRANSPOSE: i8 = 4 * OCTAVE;
+/// Velocity or CC value moved by each scroll tick
+const SCROLL_STEP: i32 = 8;
+/// ManyMouse's scroll item for the vertical wheel, 1 is the horizontal one
+const VERTICAL_WHEEL: u32 = 0;
 
 type MsgTx = mpsc::Sender<Msg>;
+type ErrorTx = mpsc::UnboundedSender<TheraminError>;
+
+/// Each error and how many times in a row it happened
+pub type ErrorLog = Vec<(TheraminError, usize)>;
 
+// settings messages are only sent on a click, not worth boxing
+#[allow(clippy::large_enum_variant)]
 pub enum Msg {
     FindNewDevices,
     ClickDev(usize),
+    SetDevSettings(usize, DevSettings),
+    /// Scala .scl file
+    LoadScale(usize, PathBuf),
+    /// Scala .kbm file
+    LoadKeyboardMap(usize, PathBuf),
+    /// Back to 12 tone over every MIDI note
+    ResetTuning(usize),
+    /// Has the second device's Y axis send the controller on the first's MIDI port, `None`
+    /// unpairs the first device
+    PairVolume(usize, Option<(usize, Controller)>),
+    /// Plays the first device through the second's handlers, `None` splits it back out
+    MergeInto(usize, Option<usize>),
+    /// Binds the next note or CC on the control port to the target, `None` stops listening
+    Learn(usize, Option<LearnTarget>),
+    Forget(usize, LearnTarget),
+    /// Keeps the device's current settings to come back to
+    SavePreset(usize),
+    LoadPreset(usize, usize),
+    DeletePreset(usize, usize),
 }
 
 pub struct TheraminMsgTx {
     tx: MsgTx,
+    err_tx: ErrorTx,
 }
 
 impl TheraminMsgTx {
+    /// Failures are reported to the error log rather than returned
     pub fn send(&self, msg: Msg) {
-        self.tx.try_send(msg).unwrap();
+        if let Err(e) = self.tx.try_send(msg) {
+            let _ = self.err_tx.send(e.into());
+        }
+    }
+
+    pub fn report(&self, err: TheraminError) {
+        let _ = self.err_tx.send(err);
     }
 }
 
@@ -31,6 +98,11 @@ type ThereminPositionsRx = watch::Receiver<ThereminPositions>;
 
 type ThereminPositions = Vec<f32>;
 
+type DriverNameRx = watch::Receiver<DriverName>;
+
+/// `None` while ManyMouse isn't initialized
+pub type DriverName = Option<String>;
+
 pub type Devices = Vec<Dev>;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -39,21 +111,794 @@ pub struct Dev {
     pub name: String,
     pub selected: bool,
     pub disconnected: bool,
+    pub settings: DevSettings,
+    /// Cut down to `settings.range`, as it's laid out on the bar
+    pub tuning: Tuning,
+    /// The device playing this one's volume and the controller it sends
+    pub volume_hand: Option<(usize, Controller)>,
+    /// The device this one is the volume hand of
+    pub volume_for: Option<usize>,
+    /// The device this one plays through
+    pub merged_into: Option<usize>,
+    /// Devices playing through this one
+    pub merged_from: Vec<usize>,
+    /// Where the octave buttons and scroll wheel have got to, the defaults while deselected
+    pub transpose: i8,
+    pub velocity: u8,
+    pub scroll_cc_value: u8,
+    /// Waiting for a control to bind to this
+    pub learning: Option<LearnTarget>,
+    pub learned: Vec<(LearnTarget, LearnSource)>,
+    pub presets: usize,
+    /// The preset last loaded
+    pub preset: Option<usize>,
 }
 
 struct DevHandlers {
     pos_idx: usize,
+    /// The settings last applied
+    settings: DevSettings,
+    tuning: Tuning,
+    /// `tuning` cut down to `settings.range`, what's actually on the bar
+    playable: Tuning,
     input_h: InputHandler,
+    /// When `input_h` was last pressed, for `MergePolicy::LastPressed`
+    pressed_at: Option<Instant>,
+    /// Semitones added to every note
+    transpose: i8,
+    /// Last value the scroll wheel sent its CC
+    scroll_cc_value: u8,
+    arpeggiator: Arpeggiator,
+    /// By voice, what each held voice is arpeggiating
+    arp_hands: Vec<Option<Hand>>,
+    /// Where the MIDI clock was last loop, if it's open
+    clock: Option<ClockPos>,
+    /// By voice, new notes held back for the next grid line
+    waiting: Vec<Option<Hand>>,
+    grid_line: Option<u64>,
     midi_h: MidiHandler,
+    /// MIDI 2.0 copy of everything sent to `midi_h`, written to `ump_file_path`
+    ump_h: Option<UmpHandler<UmpWriter<File>>>,
+    /// Takes over from ManyMouse while the device is polyphonic
+    #[cfg(target_os = "linux")]
+    touchpad: Option<Touchpad>,
+    /// By voice, what each finger on `touchpad` is playing
+    #[cfg(target_os = "linux")]
+    fingers: Vec<Option<Hand>>,
+}
+
+impl DevHandlers {
+    /// Starts with default settings, `apply_settings` brings in the device's own
+    fn new(name: &str, tuning: &Tuning) -> Result<Self> {
+        Ok(DevHandlers {
+            pos_idx: 0,
+            settings: DevSettings::default(),
+            tuning: tuning.clone(),
+            playable: tuning.clone(),
+            input_h: InputHandler::new(
+                DEFAULT_NOTE_WIDTH,
+                &slot_widths(tuning, &NoteWidths::default()),
+                MappingMode::default(),
+            ),
+            pressed_at: None,
+            transpose: 0,
+            scroll_cc_value: 0,
+            arpeggiator: Arpeggiator::new(),
+            arp_hands: Vec::new(),
+            clock: None,
+            waiting: Vec::new(),
+            grid_line: None,
+            midi_h: MidiInitialiser::new()?.virtual_port(name)?,
+            ump_h: None,
+            #[cfg(target_os = "linux")]
+            touchpad: None,
+            #[cfg(target_os = "linux")]
+            fingers: Vec::new(),
+        })
+    }
+
+    fn apply_settings(&mut self, name: &str, settings: &DevSettings) -> Result<()> {
+        let prev = std::mem::replace(&mut self.settings, *settings);
+        if settings.tuning_output != prev.tuning_output {
+            // held notes were started under the other output
+            self.release_all()?;
+            match settings.tuning_output {
+                TuningOutput::Mts => self.send_mts(&self.tuning.clone())?,
+                TuningOutput::PitchBend => self.send_mts(&Tuning::default())?,
+            }
+        }
+        if settings.hold != prev.hold {
+            // the new mode might have no way to stop a note the old one kept on
+            self.set_pedal(false)?;
+            self.release_all()?;
+            self.input_h.let_go();
+        }
+        self.input_h.set_hold(settings.hold);
+        self.input_h.mapping = settings.mapping;
+        self.input_h.hysteresis = settings.hysteresis;
+        self.input_h.sensitivity = settings.sensitivity;
+        self.input_h.set_smoothing(settings.smoothing);
+        self.input_h.curve = settings.curve;
+        self.input_h.edge = settings.edge;
+        if settings.range != prev.range {
+            self.release_all()?;
+            self.relayout();
+        } else if settings.note_widths != prev.note_widths {
+            self.relayout();
+        }
+        if settings.chord != prev.chord || settings.harmony != prev.harmony {
+            self.release_all()?;
+        }
+        if settings.arp != prev.arp {
+            self.release_all()?;
+            self.arp_hands.clear();
+            self.arpeggiator.restart();
+        }
+        if settings.merge_policy != prev.merge_policy {
+            // merged devices may have been on voices the new policy doesn't use
+            self.release_all()?;
+        }
+        self.midi_h.set_routing(settings.voice_routing)?;
+        self.midi_h.legato = settings.legato;
+        self.midi_h.set_portamento(settings.portamento)?;
+        if settings.ump_file != self.ump_h.is_some() {
+            if let Some(ump_h) = self.ump_h.as_mut() {
+                ump_h.release_all()?;
+            }
+            self.ump_h = None;
+            if settings.ump_file {
+                let file = File::create(ump_file_path(name))?;
+                let mut ump_h = UmpHandler::new(UmpWriter::new(file));
+                ump_h.set_velocity(self.midi_h.velocity);
+                self.ump_h = Some(ump_h);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if settings.polyphonic != self.touchpad.is_some() {
+            self.let_go_fingers()?;
+            self.release_all()?;
+            self.input_h.playing = false;
+            self.touchpad = None;
+            if settings.polyphonic {
+                self.touchpad = Some(Touchpad::open(
+                    name,
+                    DEFAULT_NOTE_WIDTH,
+                    &slot_widths(&self.playable, &settings.note_widths),
+                )?);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(touchpad) = self.touchpad.as_mut() {
+            touchpad.set_hysteresis(settings.hysteresis);
+            touchpad.set_smoothing(settings.smoothing);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = name;
+        Ok(())
+    }
+
+    /// Releases everything since held notes may not be in the new tuning
+    fn set_tuning(&mut self, tuning: &Tuning) -> Result<()> {
+        self.release_all()?;
+        self.tuning = tuning.clone();
+        if self.settings.tuning_output == TuningOutput::Mts {
+            self.send_mts(tuning)?;
+        }
+        self.relayout();
+        self.sync_mono()
+    }
+
+    /// Unlike `set_tuning` this keeps playing, the bar is laid out again around where the
+    /// player is and held notes move on the next sync
+    fn retune(&mut self, tuning: &Tuning) -> Result<()> {
+        self.tuning = tuning.clone();
+        if self.settings.tuning_output == TuningOutput::Mts {
+            self.send_mts(tuning)?;
+        }
+        self.relayout();
+        Ok(())
+    }
+
+    fn relayout(&mut self) {
+        self.playable = self.tuning.in_range(self.settings.range);
+        let slot_widths = slot_widths(&self.playable, &self.settings.note_widths);
+        self.input_h.set_slot_widths(&slot_widths);
+        #[cfg(target_os = "linux")]
+        if let Some(touchpad) = self.touchpad.as_mut() {
+            touchpad.set_slot_widths(&slot_widths);
+        }
+    }
+
+    /// Retunes the synth with a bulk dump for synths that only take that, then note changes
+    /// for the ones that only take those
+    fn send_mts(&mut self, tuning: &Tuning) -> Result<()> {
+        self.midi_h.send_sysex(&tuning.mts_bulk_dump())?;
+        for msg in tuning.mts_note_changes() {
+            self.midi_h.send_sysex(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn realise(&self, bar_pos: BarPos, transpose: i8) -> (Pitch, f32) {
+        realise(&self.playable, &self.settings, bar_pos, transpose)
+    }
+
+    fn chord(&self, bar_pos: BarPos, transpose: i8) -> Vec<(Pitch, f32)> {
+        chord(
+            &self.tuning,
+            &self.playable,
+            &self.settings,
+            bar_pos,
+            transpose,
+        )
+    }
+
+    /// Bends each note of the chord by its own amount, the rest of `expression` is shared
+    fn express_chord(
+        &mut self,
+        voice: usize,
+        bar_pos: BarPos,
+        transpose: i8,
+        expression: Expression,
+    ) -> Result<()> {
+        let notes = self.chord(bar_pos, transpose);
+        for (chord_voice, (_, bend)) in chord_voices(voice).zip(notes) {
+            self.express_voice(chord_voice, Expression { bend, ..expression })?;
+        }
+        Ok(())
+    }
+
+    /// Releases whatever the chord had before that it doesn't now
+    fn play_chord(&mut self, voice: usize, bar_pos: BarPos, transpose: i8) -> Result<()> {
+        let mut notes = self.chord(bar_pos, transpose).into_iter();
+        for chord_voice in chord_voices(voice) {
+            match notes.next() {
+                Some((note, _)) => self.play_voice(chord_voice, note)?,
+                None => self.release_voice(chord_voice)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn release_chord(&mut self, voice: usize) -> Result<()> {
+        chord_voices(voice).try_for_each(|chord_voice| self.release_voice(chord_voice))
+    }
+
+    fn transpose_by(&mut self, semitones: i8) {
+        self.transpose = transposed(self.transpose, semitones);
+    }
+
+    fn set_velocity(&mut self, velocity: u8) {
+        self.midi_h.velocity = velocity;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.set_velocity(velocity);
+        }
+    }
+
+    fn panic(&mut self) -> Result<()> {
+        self.input_h.let_go();
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.set_pedal(false)?;
+            ump_h.release_all()?;
+        }
+        self.midi_h.panic()
+    }
+
+    fn set_pedal(&mut self, down: bool) -> Result<()> {
+        self.midi_h.set_pedal(down)?;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.set_pedal(down)?;
+        }
+        Ok(())
+    }
+
+    fn play_voice(&mut self, voice: usize, pitch: Pitch) -> Result<()> {
+        self.midi_h.play_voice(voice, pitch)?;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.play_voice(voice, pitch)?;
+        }
+        Ok(())
+    }
+
+    fn release_voice(&mut self, voice: usize) -> Result<()> {
+        self.midi_h.release_voice(voice)?;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.release_voice(voice)?;
+        }
+        Ok(())
+    }
+
+    fn express_voice(&mut self, voice: usize, expression: Expression) -> Result<()> {
+        self.midi_h.express_voice(voice, expression)?;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.express_voice(voice, expression)?;
+        }
+        Ok(())
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        self.midi_h.release_all()?;
+        if let Some(ump_h) = self.ump_h.as_mut() {
+            ump_h.release_all()?;
+        }
+        Ok(())
+    }
+
+    fn hand(&self) -> Hand {
+        let sounding = self.input_h.sounding();
+        Hand {
+            bar_pos: sounding.unwrap_or(self.input_h.bar_pos()),
+            expression: self.input_h.expression(),
+            playing: sounding.is_some(),
+            pressed_at: self.pressed_at,
+            transpose: self.transpose,
+        }
+    }
+
+    fn sync_mono(&mut self) -> Result<()> {
+        self.sync_voice(0, &self.hand())
+    }
+
+    /// `hands` starts with this device's own, the rest are the devices merged into it in the
+    /// order they were merged, `None` for ones that are deselected or polyphonic so every
+    /// device keeps its voice
+    fn sync_merged(&mut self, hands: &[Option<Hand>]) -> Result<()> {
+        let held = hands.iter().flatten().filter(|hand| hand.playing);
+        let heard = match self.settings.merge_policy {
+            MergePolicy::All => {
+                for (voice, hand) in hands.iter().enumerate() {
+                    match hand {
+                        Some(hand) => self.sync_voice(voice, hand)?,
+                        None => self.drop_voice(voice)?,
+                    }
+                }
+                return Ok(());
+            }
+            MergePolicy::LastPressed => held.max_by_key(|hand| hand.pressed_at),
+            MergePolicy::HighestNote => held.max_by(|a, b| {
+                let pitch = |hand: &Hand| {
+                    let (note, bend) = self.realise(hand.bar_pos, hand.transpose);
+                    note as f32 + bend
+                };
+                pitch(a).total_cmp(&pitch(b))
+            }),
+        };
+        // with nothing held the voice follows this device, ready for its next press
+        match heard.or(hands[0].as_ref()) {
+            Some(hand) => self.sync_voice(0, &hand.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Ends a voice whose device no longer plays through these handlers
+    fn drop_voice(&mut self, voice: usize) -> Result<()> {
+        if let Some(arp_hand) = self.arp_hands.get_mut(voice) {
+            *arp_hand = None;
+        }
+        if let Some(waiting) = self.waiting.get_mut(voice) {
+            *waiting = None;
+        }
+        self.release_chord(voice)
+    }
+
+    /// Brings a voice in line with `hand`, expression first so a new note starts from where
+    /// the old one glided to
+    fn sync_voice(&mut self, voice: usize, hand: &Hand) -> Result<()> {
+        if let Some(controller) = self.settings.y_controller {
+            self.midi_h
+                .set_controller(controller, hand.expression.timbre)?;
+        }
+        self.gate_voice(voice, hand)
+    }
+
+    /// Held for the arpeggiator or the next grid line, otherwise played straight away
+    fn gate_voice(&mut self, voice: usize, hand: &Hand) -> Result<()> {
+        if self.settings.arp.is_some() {
+            return self.hold_for_arp(voice, hand);
+        }
+        if self.quantizing() {
+            if voice >= self.waiting.len() {
+                self.waiting.resize(voice + 1, None);
+            }
+            // a press or crossing into another slot waits, moving within one doesn't
+            let (note, _) = self.realise(hand.bar_pos, hand.transpose);
+            let new_note =
+                hand.playing && self.midi_h.voice_note(chord_voices(voice).start) != Some(note);
+            self.waiting[voice] = new_note.then_some(*hand);
+            if new_note {
+                return Ok(());
+            }
+        }
+        self.play_hand(voice, hand)
+    }
+
+    /// Fingers go through the same arpeggiator and quantizing as a mouse, each on its own
+    /// voice
+    #[cfg(target_os = "linux")]
+    fn sync_finger(&mut self, voice_ev: VoiceEvent) -> Result<()> {
+        let (VoiceEvent::Play(voice, _)
+        | VoiceEvent::Release(voice)
+        | VoiceEvent::Express(voice, ..)) = voice_ev;
+        if voice >= self.fingers.len() {
+            self.fingers.resize(voice + 1, None);
+        }
+        let finger = self.fingers[voice];
+        let hand = match voice_ev {
+            VoiceEvent::Play(_, bar_pos) => Hand {
+                bar_pos,
+                expression: finger.map_or(Expression::default(), |finger| finger.expression),
+                playing: true,
+                // fingers aren't merged, so nothing compares when they were pressed
+                pressed_at: None,
+                transpose: self.transpose,
+            },
+            VoiceEvent::Express(_, bar_pos, expression) => Hand {
+                bar_pos,
+                expression,
+                playing: finger.is_some_and(|finger| finger.playing),
+                pressed_at: None,
+                transpose: self.transpose,
+            },
+            VoiceEvent::Release(_) => match finger {
+                Some(finger) => Hand {
+                    playing: false,
+                    ..finger
+                },
+                None => return self.drop_voice(voice),
+            },
+        };
+        self.fingers[voice] = hand.playing.then_some(hand);
+        self.gate_voice(voice, &hand)
+    }
+
+    /// Ends every finger's voice, for when the touchpad goes away
+    #[cfg(target_os = "linux")]
+    fn let_go_fingers(&mut self) -> Result<()> {
+        let fingers = std::mem::take(&mut self.fingers).len();
+        (0..fingers).try_for_each(|voice| self.drop_voice(voice))
+    }
+
+    fn play_hand(&mut self, voice: usize, hand: &Hand) -> Result<()> {
+        self.express_chord(voice, hand.bar_pos, hand.transpose, hand.expression)?;
+        if hand.playing {
+            self.play_chord(voice, hand.bar_pos, hand.transpose)
+        } else {
+            self.release_chord(voice)
+        }
+    }
+
+    fn quantizing(&self) -> bool {
+        self.settings.quantize.is_some() && self.clock.is_some_and(|clock| clock.running)
+    }
+
+    /// Catches up with the MIDI clock and the arpeggiator's tempo
+    fn tick(&mut self, now: Instant, clock: Option<ClockPos>) -> Result<()> {
+        self.clock = clock;
+        self.play_waiting()?;
+        let clock_ticks = clock.filter(|clock| clock.running).map(|clock| clock.ticks);
+        self.step_arp(now, clock_ticks)
+    }
+
+    /// Plays the notes held back once a grid line goes by, or straight away when there's no
+    /// longer a grid to wait for
+    fn play_waiting(&mut self) -> Result<()> {
+        let line = match (self.settings.quantize, self.clock) {
+            (Some(grid), Some(clock)) if clock.running => Some(clock.ticks / grid.ticks()),
+            _ => None,
+        };
+        let last_line = std::mem::replace(&mut self.grid_line, line);
+        if line.is_some() && line == last_line {
+            return Ok(());
+        }
+        for voice in 0..self.waiting.len() {
+            if let Some(hand) = self.waiting[voice].take() {
+                self.play_hand(voice, &hand)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Held voices are left for `step_arp` to play
+    fn hold_for_arp(&mut self, voice: usize, hand: &Hand) -> Result<()> {
+        if voice >= self.arp_hands.len() {
+            self.arp_hands.resize(voice + 1, None);
+        }
+        if !hand.playing {
+            self.arp_hands[voice] = None;
+            return self.release_chord(voice);
+        }
+        if self.arp_hands.iter().all(Option::is_none) {
+            self.arpeggiator.restart();
+        }
+        self.arp_hands[voice] = Some(*hand);
+        Ok(())
+    }
+
+    /// Plays the next note of every held voice's arpeggio if it's due
+    fn step_arp(&mut self, now: Instant, clock_ticks: Option<u64>) -> Result<()> {
+        let Some(arp) = self.settings.arp else {
+            return Ok(());
+        };
+        if self.arp_hands.iter().all(Option::is_none) {
+            return Ok(());
+        }
+        let Some(step) = self.arpeggiator.due(&arp, now, clock_ticks) else {
+            return Ok(());
+        };
+        for voice in 0..self.arp_hands.len() {
+            let Some(hand) = self.arp_hands[voice] else {
+                continue;
+            };
+            let notes = self.arp_notes(&hand);
+            if notes.is_empty() {
+                continue;
+            }
+            let (note, bend) = notes[self.arpeggiator.pick(arp.pattern, step, notes.len())];
+            let arp_voice = chord_voices(voice).start;
+            // retriggered even if it's the same note again
+            self.release_voice(arp_voice)?;
+            self.express_voice(
+                arp_voice,
+                Expression {
+                    bend,
+                    ..hand.expression
+                },
+            )?;
+            self.play_voice(arp_voice, note)?;
+        }
+        Ok(())
+    }
+
+    /// The chord if there is one, otherwise a run up the scale from the note played, low to
+    /// high
+    fn arp_notes(&self, hand: &Hand) -> Vec<(Pitch, f32)> {
+        let mut notes = self.chord(hand.bar_pos, hand.transpose);
+        if notes.len() == 1 {
+            let slots = self.playable.slots().len();
+            notes = (0..ARP_SCALE_NOTES)
+                .map(|degrees| BarPos {
+                    slot: hand.bar_pos.slot + degrees,
+                    pos: hand.bar_pos.pos + degrees as f32,
+                })
+                .filter(|pos| pos.slot < slots)
+                .map(|pos| self.realise(pos, hand.transpose))
+                .collect();
+        }
+        notes.sort_by(|a, b| (a.0 as f32 + a.1).total_cmp(&(b.0 as f32 + b.1)));
+        notes
+    }
+
+    /// Puts the synth back in 12 tone if it was retuned
+    fn close(mut self) -> Result<()> {
+        let mut released = self.ump_h.as_mut().map_or(Ok(()), UmpHandler::release_all);
+        if self.settings.tuning_output == TuningOutput::Mts {
+            released = released.and(self.send_mts(&Tuning::default()));
+        }
+        self.midi_h.close().and(released)
+    }
+
+    fn is_polyphonic(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return self.touchpad.is_some();
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+}
+
+/// What a device's `InputHandler` is playing, so merged devices can be played through
+/// another's handlers
+#[derive(Clone, Copy)]
+struct Hand {
+    bar_pos: BarPos,
+    expression: Expression,
+    playing: bool,
+    pressed_at: Option<Instant>,
+    transpose: i8,
+}
+
+/// Only MPE has the bend range to glide between slots, elsewhere bends just tune each one.
+/// Transposing moves the MIDI note, so under MTS it's by keys rather than semitones
+fn realise(
+    playable: &Tuning,
+    settings: &DevSettings,
+    bar_pos: BarPos,
+    transpose: i8,
+) -> (Pitch, f32) {
+    let glide = settings.voice_routing == VoiceRouting::Mpe;
+    let (note, bend) = playable.realise(bar_pos, glide, settings.tuning_output);
+    let note = (note as i16 + transpose as i16).clamp(0, HIGHEST_MIDI_NOTE as i16);
+    (note as Pitch, bend)
+}
+
+/// The note `bar_pos` plays followed by the rest of its chord and harmony, leaving out notes
+/// off the bar or out of MIDI's range and repeats. `tuning` is the whole of it, so a narrow
+/// range doesn't change what the chord is
+fn chord(
+    tuning: &Tuning,
+    playable: &Tuning,
+    settings: &DevSettings,
+    bar_pos: BarPos,
+    transpose: i8,
+) -> Vec<(Pitch, f32)> {
+    let root = realise(playable, settings, bar_pos, transpose);
+    let mut notes = vec![root];
+    let tones = settings.chord.tones(tuning.slots_per_period()).into_iter();
+    for tone in tones.chain(settings.harmony.tones()) {
+        let note = match tone {
+            ChordTone::Degrees(degrees) => {
+                let slot = bar_pos.slot as i32 + degrees as i32;
+                if slot < 0 || slot as usize >= playable.slots().len() {
+                    continue;
+                }
+                let pos = BarPos {
+                    slot: slot as usize,
+                    pos: bar_pos.pos + degrees as f32,
+                };
+                realise(playable, settings, pos, transpose)
+            }
+            ChordTone::Semitones(semitones) => {
+                let note = root.0 as i16 + semitones as i16;
+                if !(0..=HIGHEST_MIDI_NOTE as i16).contains(&note) {
+                    continue;
+                }
+                (note as Pitch, root.1)
+            }
+        };
+        if notes.iter().all(|&(played, _)| played != note.0) {
+            notes.push(note);
+        }
+    }
+    notes
+}
+
+/// `transpose` moved by `semitones`, kept within `MAX_TRANSPOSE` either way
+fn transposed(transpose: i8, semitones: i8) -> i8 {
+    transpose
+        .saturating_add(semitones)
+        .clamp(-MAX_TRANSPOSE, MAX_TRANSPOSE)
+}
+
+/// Each note of a voice's chord is played on a voice of its own
+fn chord_voices(voice: usize) -> std::ops::Range<usize> {
+    voice * MAX_CHORD_NOTES..(voice + 1) * MAX_CHORD_NOTES
+}
+
+fn slot_widths(playable: &Tuning, note_widths: &NoteWidths) -> Vec<u8> {
+    playable
+        .slots()
+        .iter()
+        .map(|slot| note_widths.get(slot.key))
+        .collect()
 }
 
 struct DevState {
     name: String,
     selected: Option<DevHandlers>,
     disconnected: bool,
+    settings: DevSettings,
+    /// Files loaded, kept so a scale and keyboard map can be loaded one at a time
+    scale: Option<Scale>,
+    kbm: Option<KeyboardMap>,
+    tuning: Tuning,
+    presets: Vec<DevSettings>,
+    preset: Option<usize>,
+    /// Loaded from files, oldest first
+    recent_scales: Vec<Scale>,
+    /// Brought back when the arpeggiator's toggled on again
+    last_arp: Arp,
+    /// Where chords cycle round to after sevenths, once there's been some
+    fixed_chord: Option<[Option<i8>; CHORD_EXTRA_NOTES]>,
+}
+
+impl DevState {
+    /// Keeps the old tuning if the new files don't make a playable one, `keys` are the ones
+    /// last held on the keyboard port
+    fn set_tuning_files(
+        &mut self,
+        scale: Option<Scale>,
+        kbm: Option<KeyboardMap>,
+        keys: &[Pitch],
+    ) -> Result<()> {
+        let tuning = match self
+            .settings
+            .follow_keys
+            .tuning(keys, scale.as_ref(), kbm.as_ref())?
+        {
+            Some(tuning) => tuning,
+            None => match (&scale, &kbm) {
+                (None, None) => Tuning::default(),
+                (scale, kbm) => Tuning::new(
+                    &scale.clone().unwrap_or_default(),
+                    &kbm.clone().unwrap_or_default(),
+                )?,
+            },
+        };
+        self.scale = scale;
+        self.kbm = kbm;
+        self.tuning = tuning;
+        match self.selected.as_mut() {
+            Some(handlers) => handlers.set_tuning(&self.tuning),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the device was playing and needs syncing to the new tuning
+    fn follow_keys(&mut self, keys: &[Pitch]) -> Result<bool> {
+        let tuning =
+            self.settings
+                .follow_keys
+                .tuning(keys, self.scale.as_ref(), self.kbm.as_ref())?;
+        let Some(tuning) = tuning else {
+            return Ok(false);
+        };
+        self.tuning = tuning;
+        match self.selected.as_mut() {
+            Some(handlers) => handlers.retune(&self.tuning).map(|()| true),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Senders for everything the worker reports back to the GUI
+struct GuiTx {
+    devs: watch::Sender<Devices>,
+    positions: watch::Sender<ThereminPositions>,
+    driver_name: watch::Sender<DriverName>,
+}
+
+/// A device told apart from others with the same name by how many come before it, so it can
+/// be found again after a refresh
+type DevKey = (String, usize);
+
+fn dev_key(dev_states: &[DevState], i: usize) -> DevKey {
+    let name = dev_states[i].name.clone();
+    let nth = dev_states[..i].iter().filter(|d| d.name == name).count();
+    (name, nth)
+}
+
+fn dev_index(dev_states: &[DevState], key: &DevKey) -> Option<usize> {
+    dev_states
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| d.name == key.0)
+        .nth(key.1)
+        .map(|(i, _)| i)
+}
+
+/// Two devices played as one theremin, like a theremin's pitch and volume antennas
+struct Pairing {
+    pitch: DevKey,
+    volume: DevKey,
+    controller: Controller,
+    /// The volume device is never selected, so its Y axis is read here instead of by handlers
+    /// with a MIDI port of their own
+    volume_h: InputHandler,
+}
+
+/// A device playing through another's handlers
+struct Merge {
+    from: DevKey,
+    into: DevKey,
 }
 
-fn gui_devices_from_states(dev_states: &[DevState]) -> Devices {
+struct Learned {
+    source: LearnSource,
+    dev: DevKey,
+    target: LearnTarget,
+}
+
+#[derive(Default)]
+struct Learns {
+    learning: Option<(DevKey, LearnTarget)>,
+    learned: Vec<Learned>,
+}
+
+fn gui_devices_from_states(
+    dev_states: &[DevState],
+    pairs: &[Pairing],
+    merges: &[Merge],
+    learns: &Learns,
+) -> Devices {
     dev_states
         .iter()
         .enumerate()
@@ -62,33 +907,1037 @@ fn gui_devices_from_states(dev_states: &[DevState]) -> Devices {
             name: d_s.name.clone(),
             selected: d_s.selected.is_some(),
             disconnected: d_s.disconnected,
+            settings: d_s.settings,
+            tuning: d_s.tuning.in_range(d_s.settings.range),
+            volume_hand: pairs
+                .iter()
+                .find(|pair| pair.pitch == dev_key(dev_states, i))
+                .and_then(|pair| Some((dev_index(dev_states, &pair.volume)?, pair.controller))),
+            volume_for: pairs
+                .iter()
+                .find(|pair| pair.volume == dev_key(dev_states, i))
+                .and_then(|pair| dev_index(dev_states, &pair.pitch)),
+            merged_into: merges
+                .iter()
+                .find(|merge| merge.from == dev_key(dev_states, i))
+                .and_then(|merge| dev_index(dev_states, &merge.into)),
+            merged_from: merges
+                .iter()
+                .filter(|merge| merge.into == dev_key(dev_states, i))
+                .filter_map(|merge| dev_index(dev_states, &merge.from))
+                .collect(),
+            transpose: d_s.selected.as_ref().map_or(0, |h| h.transpose),
+            velocity: d_s.selected.as_ref().map_or(VEL, |h| h.midi_h.velocity),
+            scroll_cc_value: d_s.selected.as_ref().map_or(0, |h| h.scroll_cc_value),
+            learning: learns
+                .learning
+                .as_ref()
+                .filter(|(dev, _)| *dev == dev_key(dev_states, i))
+                .map(|(_, target)| *target),
+            learned: learns
+                .learned
+                .iter()
+                .filter(|learned| learned.dev == dev_key(dev_states, i))
+                .map(|learned| (learned.target, learned.source))
+                .collect(),
+            presets: d_s.presets.len(),
+            preset: d_s.preset,
         })
         .collect()
 }
 
 struct State {
-    m_mouse: ManyMouse,
+    m_mouse: Option<ManyMouse>,
     dev_states: Vec<DevState>,
+    /// Kept through refreshes, pairs whose devices are missing just do nothing
+    pairs: Vec<Pairing>,
+    /// Kept the same way as `pairs`
+    merges: Vec<Merge>,
+    /// Opened once a device's arpeggio or quantizing follows it, then kept through refreshes
+    clock: Option<MidiClock>,
+    /// Opened once a device follows the keyboard, kept the same way as `clock`
+    keys: Option<KeysIn>,
+    /// Kept the same way as `pairs`
+    learns: Learns,
+    /// Opened on the first learn click, kept the same way as `clock`
+    controls: Option<ControlIn>,
 }
 
 impl State {
-    fn new(devs_tx: &watch::Sender<Devices>, pos_tx: &watch::Sender<ThereminPositions>) -> Self {
-        let m_mouse = ManyMouse::new();
+    /// If ManyMouse fails to initialize the state has no devices, and the error is returned
+    /// alongside it so a later refresh can try again
+    fn new(gui_tx: &GuiTx) -> (Self, Result<()>) {
+        let (m_mouse, res) = match ManyMouse::new() {
+            Ok(m_mouse) => (Some(m_mouse), Ok(())),
+            Err(e) => (None, Err(e)),
+        };
         let dev_states: Vec<DevState> = m_mouse
-            .device_list()
-            .into_iter()
+            .iter()
+            .flat_map(|m_mouse| m_mouse.device_list())
             .map(|name| DevState {
                 name,
                 selected: None,
                 disconnected: false,
+                settings: DevSettings::default(),
+                scale: None,
+                kbm: None,
+                tuning: Tuning::default(),
+                presets: Vec::new(),
+                preset: None,
+                recent_scales: Vec::new(),
+                last_arp: Arp::default(),
+                fixed_chord: None,
             })
             .collect();
-        devs_tx.send(gui_devices_from_states(&dev_states)).unwrap();
-        pos_tx.send(Vec::new()).unwrap();
-        State {
+        gui_tx
+            .driver_name
+            .send_replace(m_mouse.as_ref().map(ManyMouse::driver_name));
+        gui_tx.devs.send_replace(gui_devices_from_states(
+            &dev_states,
+            &[],
+            &[],
+            &Learns::default(),
+        ));
+        gui_tx.positions.send_replace(Vec::new());
+        let state = State {
             m_mouse,
             dev_states,
+            pairs: Vec::new(),
+            merges: Vec::new(),
+            clock: None,
+            keys: None,
+            learns: Learns::default(),
+            controls: None,
+        };
+        (state, res)
+    }
+
+    fn close_all(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        for handlers in self.dev_states.iter_mut().filter_map(|d| d.selected.take()) {
+            if let Err(e) = handlers.close() {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    fn handle_msg(&mut self, msg: Msg, gui_tx: &GuiTx) -> Result<()> {
+        match msg {
+            Msg::FindNewDevices => {
+                let closed = self.close_all();
+                self.m_mouse.take();
+                let (mut new_state, res) = State::new(gui_tx);
+                // settings and tuning stay with the device, found again the same way as pairs
+                for i in 0..new_state.dev_states.len() {
+                    let key = dev_key(&new_state.dev_states, i);
+                    let Some(old) = dev_index(&self.dev_states, &key) else {
+                        continue;
+                    };
+                    let old = &mut self.dev_states[old];
+                    let dev_state = &mut new_state.dev_states[i];
+                    dev_state.settings = old.settings;
+                    dev_state.scale = old.scale.take();
+                    dev_state.kbm = old.kbm.take();
+                    dev_state.tuning = std::mem::take(&mut old.tuning);
+                    dev_state.presets = std::mem::take(&mut old.presets);
+                    dev_state.preset = old.preset;
+                    dev_state.recent_scales = std::mem::take(&mut old.recent_scales);
+                    dev_state.last_arp = old.last_arp;
+                    dev_state.fixed_chord = old.fixed_chord;
+                }
+                let pairs = std::mem::take(&mut self.pairs);
+                let merges = std::mem::take(&mut self.merges);
+                let clock = self.clock.take();
+                let keys = self.keys.take();
+                let learns = std::mem::take(&mut self.learns);
+                let controls = self.controls.take();
+                *self = State {
+                    pairs,
+                    merges,
+                    clock,
+                    keys,
+                    learns,
+                    controls,
+                    ..new_state
+                };
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                closed.and(res)
+            }
+            Msg::ClickDev(i) => {
+                // the device list may have been refreshed since the GUI sent this
+                if i >= self.dev_states.len() {
+                    return Ok(());
+                }
+                // a volume hand only plays through its pair
+                let key = dev_key(&self.dev_states, i);
+                if self.pairs.iter().any(|pair| pair.volume == key) {
+                    return Ok(());
+                }
+                let dev_state = &mut self.dev_states[i];
+                let res = match dev_state.selected.take() {
+                    Some(handlers) => handlers.close(),
+                    None => {
+                        DevHandlers::new(&dev_state.name, &dev_state.tuning).and_then(|handlers| {
+                            dev_state
+                                .selected
+                                .insert(handlers)
+                                .apply_settings(&dev_state.name, &dev_state.settings)
+                        })
+                    }
+                };
+                self.reindex(gui_tx);
+                res
+            }
+            Msg::SetDevSettings(i, settings) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                let prev = std::mem::replace(&mut dev_state.settings, settings);
+                if let Some(arp) = settings.arp {
+                    dev_state.last_arp = arp;
+                }
+                if let ChordMode::Fixed(intervals) = settings.chord {
+                    dev_state.fixed_chord = Some(intervals);
+                }
+                let mut res = match dev_state.selected.as_mut() {
+                    Some(handlers) => handlers.apply_settings(&dev_state.name, &settings),
+                    None => Ok(()),
+                };
+                let follows_clock = settings.quantize.is_some()
+                    || settings
+                        .arp
+                        .is_some_and(|arp| arp.tempo == ArpTempo::MidiClock);
+                if follows_clock && self.clock.is_none() {
+                    match MidiClock::open(CLOCK_PORT_NAME) {
+                        Ok(clock) => self.clock = Some(clock),
+                        Err(e) => res = res.and(Err(e)),
+                    }
+                }
+                if settings.follow_keys != FollowKeys::Off && self.keys.is_none() {
+                    match KeysIn::open(KEYS_PORT_NAME) {
+                        Ok(keys) => self.keys = Some(keys),
+                        Err(e) => res = res.and(Err(e)),
+                    }
+                }
+                if settings.follow_keys != prev.follow_keys {
+                    let keys = self.keys.as_ref().map_or(&[][..], KeysIn::last_held);
+                    let (scale, kbm) = (dev_state.scale.clone(), dev_state.kbm.clone());
+                    res = res.and(dev_state.set_tuning_files(scale, kbm, keys));
+                }
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::LoadScale(i, path) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                let kbm = dev_state.kbm.clone();
+                let keys = self.keys.as_ref().map_or(&[][..], KeysIn::last_held);
+                let res = Scale::load(&path).and_then(|scale| {
+                    dev_state.set_tuning_files(Some(scale.clone()), kbm, keys)?;
+                    // loading one again moves it to the end
+                    dev_state.recent_scales.retain(|recent| *recent != scale);
+                    dev_state.recent_scales.push(scale);
+                    let excess = dev_state
+                        .recent_scales
+                        .len()
+                        .saturating_sub(MAX_RECENT_SCALES);
+                    dev_state.recent_scales.drain(..excess);
+                    Ok(())
+                });
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::LoadKeyboardMap(i, path) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                let scale = dev_state.scale.clone();
+                let keys = self.keys.as_ref().map_or(&[][..], KeysIn::last_held);
+                let res = KeyboardMap::load(&path)
+                    .and_then(|kbm| dev_state.set_tuning_files(scale, Some(kbm), keys));
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::ResetTuning(i) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                let keys = self.keys.as_ref().map_or(&[][..], KeysIn::last_held);
+                let res = dev_state.set_tuning_files(None, None, keys);
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::PairVolume(i, volume) => {
+                if i >= self.dev_states.len() {
+                    return Ok(());
+                }
+                // a device is only ever in one pair
+                let pitch = dev_key(&self.dev_states, i);
+                self.pairs
+                    .retain(|pair| pair.pitch != pitch && pair.volume != pitch);
+                let mut res = Ok(());
+                if let Some((v, controller)) = volume {
+                    if v < self.dev_states.len() && v != i {
+                        let volume = dev_key(&self.dev_states, v);
+                        self.pairs
+                            .retain(|pair| pair.pitch != volume && pair.volume != volume);
+                        let volume_state = &mut self.dev_states[v];
+                        if let Some(handlers) = volume_state.selected.take() {
+                            res = handlers.close();
+                        }
+                        let volume_h = InputHandler::new(
+                            DEFAULT_NOTE_WIDTH,
+                            &[],
+                            volume_state.settings.mapping,
+                        );
+                        self.pairs.push(Pairing {
+                            pitch,
+                            volume,
+                            controller,
+                            volume_h,
+                        });
+                    }
+                }
+                self.reindex(gui_tx);
+                res
+            }
+            Msg::MergeInto(i, into) => {
+                if i >= self.dev_states.len() {
+                    return Ok(());
+                }
+                let from = dev_key(&self.dev_states, i);
+                let mut res = self.release_merged(&from);
+                self.merges.retain(|merge| merge.from != from);
+                if let Some(into) = into.filter(|&into| into < self.dev_states.len()) {
+                    // merge into whatever the target plays through so there's only one level
+                    let into = self
+                        .merges
+                        .iter()
+                        .find(|merge| merge.from == dev_key(&self.dev_states, into))
+                        .map_or_else(
+                            || dev_key(&self.dev_states, into),
+                            |merge| merge.into.clone(),
+                        );
+                    if into != from {
+                        for merge in self.merges.iter_mut().filter(|merge| merge.into == from) {
+                            merge.into = into.clone();
+                        }
+                        self.merges.push(Merge { from, into });
+                    }
+                }
+                // and whatever it plays through now
+                res = res.and(self.release_merged(&dev_key(&self.dev_states, i)));
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::Learn(i, target) => {
+                if i >= self.dev_states.len() {
+                    return Ok(());
+                }
+                let mut res = Ok(());
+                if target.is_some() && self.controls.is_none() {
+                    match ControlIn::open(CONTROL_PORT_NAME) {
+                        Ok(controls) => self.controls = Some(controls),
+                        Err(e) => res = Err(e),
+                    }
+                }
+                // anything touched while nobody was listening shouldn't get learned
+                if let Some(controls) = self.controls.as_ref() {
+                    controls.poll().for_each(drop);
+                }
+                self.learns.learning = target.map(|target| (dev_key(&self.dev_states, i), target));
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                res
+            }
+            Msg::Forget(i, target) => {
+                if i >= self.dev_states.len() {
+                    return Ok(());
+                }
+                let dev = dev_key(&self.dev_states, i);
+                self.learns
+                    .learned
+                    .retain(|learned| learned.dev != dev || learned.target != target);
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                Ok(())
+            }
+            Msg::SavePreset(i) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                dev_state.presets.push(dev_state.settings);
+                dev_state.preset = Some(dev_state.presets.len() - 1);
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                Ok(())
+            }
+            Msg::LoadPreset(i, preset) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                let Some(&loaded) = dev_state.presets.get(preset) else {
+                    return Ok(());
+                };
+                dev_state.preset = Some(preset);
+                // the buttons stay put, so the one stepping through presets keeps working
+                let settings = DevSettings {
+                    buttons: dev_state.settings.buttons,
+                    ..loaded
+                };
+                self.handle_msg(Msg::SetDevSettings(i, settings), gui_tx)
+            }
+            Msg::DeletePreset(i, preset) => {
+                let Some(dev_state) = self.dev_states.get_mut(i) else {
+                    return Ok(());
+                };
+                if preset >= dev_state.presets.len() {
+                    return Ok(());
+                }
+                dev_state.presets.remove(preset);
+                dev_state.preset = match dev_state.preset {
+                    Some(p) if p == preset => None,
+                    Some(p) if p > preset => Some(p - 1),
+                    p => p,
+                };
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                Ok(())
+            }
+        }
+    }
+
+    /// Numbers the selected devices' positions again after one's been opened or closed
+    fn reindex(&mut self, gui_tx: &GuiTx) {
+        self.dev_states
+            .iter_mut()
+            .filter_map(|d| d.selected.as_mut())
+            .enumerate()
+            .for_each(|(i, selected)| selected.pos_idx = i);
+        gui_tx.devs.send_replace(gui_devices_from_states(
+            &self.dev_states,
+            &self.pairs,
+            &self.merges,
+            &self.learns,
+        ));
+        let new_len = self
+            .dev_states
+            .iter()
+            .filter(|d| d.selected.is_some())
+            .count();
+        gui_tx
+            .positions
+            .send_modify(|positions| positions.resize(new_len, 0.0));
+    }
+
+    /// Notes held for merged devices may end up on the wrong voice when the merges change
+    fn release_merged(&mut self, key: &DevKey) -> Result<()> {
+        let into = self
+            .merges
+            .iter()
+            .find(|merge| &merge.from == key)
+            .map_or(key, |merge| &merge.into);
+        let handlers =
+            dev_index(&self.dev_states, into).and_then(|i| self.dev_states[i].selected.as_mut());
+        match handlers {
+            Some(handlers) => handlers.release_all(),
+            None => Ok(()),
+        }
+    }
+
+    /// The device whose handlers device `i` plays through, `None` if that one has gone
+    fn merged_into(&self, i: usize) -> Option<usize> {
+        let key = dev_key(&self.dev_states, i);
+        match self.merges.iter().find(|merge| merge.from == key) {
+            Some(merge) => dev_index(&self.dev_states, &merge.into),
+            None => Some(i),
+        }
+    }
+
+    /// Plays device `i` through whichever handlers it's merged into
+    fn sync(&mut self, i: usize) -> Result<()> {
+        let Some(into_i) = self.merged_into(i) else {
+            return Ok(());
+        };
+        let into = dev_key(&self.dev_states, into_i);
+        let merged: Vec<Option<usize>> = self
+            .merges
+            .iter()
+            .filter(|merge| merge.into == into)
+            .map(|merge| dev_index(&self.dev_states, &merge.from))
+            .collect();
+        let hands: Vec<Option<Hand>> = std::iter::once(Some(into_i))
+            .chain(merged)
+            .map(|i| {
+                let handlers = self.dev_states[i?].selected.as_ref()?;
+                (!handlers.is_polyphonic()).then(|| handlers.hand())
+            })
+            .collect();
+        let Some(handlers) = self.dev_states[into_i].selected.as_mut() else {
+            return Ok(());
+        };
+        if handlers.is_polyphonic() {
+            return Ok(());
+        }
+        handlers.sync_merged(&hands)
+    }
+
+    fn handle_event(&mut self, ev: manymouse::Event, gui_tx: &GuiTx) -> Result<()> {
+        if let Some(p) = self
+            .pairs
+            .iter()
+            .position(|pair| dev_index(&self.dev_states, &pair.volume) == Some(ev.device as usize))
+        {
+            return self.handle_volume_event(ev, p);
         }
+        let Some(handlers) = self
+            .dev_states
+            .get_mut(ev.device as usize)
+            .and_then(|d| d.selected.as_mut())
+        else {
+            return Ok(());
+        };
+        if handlers.is_polyphonic() {
+            return Ok(());
+        }
+        let input_h = &mut handlers.input_h;
+        let relative = input_h.mapping == MappingMode::Relative;
+        let (x, y) = (Axis::X as u32, Axis::Y as u32);
+        match ev.ev_type {
+            manymouse::EventType::Relmotion if ev.item == x && relative => {
+                input_h.handle_rel_move(ev.value);
+            }
+            manymouse::EventType::Absmotion if ev.item == x && !relative => {
+                input_h.handle_abs_move(ev.value, ev.minval, ev.maxval);
+            }
+            manymouse::EventType::Relmotion if ev.item == y && relative => {
+                input_h.handle_rel_y(ev.value);
+            }
+            manymouse::EventType::Absmotion if ev.item == y && !relative => {
+                input_h.handle_abs_y(ev.value, ev.minval, ev.maxval);
+            }
+            manymouse::EventType::Scroll if ev.item == VERTICAL_WHEEL => {
+                return self.handle_scroll(ev.device as usize, ev.value, gui_tx);
+            }
+            manymouse::EventType::Button => {
+                return match handlers.settings.buttons.get(ev.item) {
+                    Some(action) => {
+                        self.handle_button(ev.device as usize, action, ev.value == 1, gui_tx)
+                    }
+                    None => Ok(()),
+                };
+            }
+            _ => return Ok(()),
+        }
+        gui_tx
+            .positions
+            .send_modify(|positions| positions[handlers.pos_idx] = input_h.float_pos());
+        self.sync(ev.device as usize)
+    }
+
+    fn handle_button(
+        &mut self,
+        i: usize,
+        action: ButtonAction,
+        pressed: bool,
+        gui_tx: &GuiTx,
+    ) -> Result<()> {
+        if action == ButtonAction::Panic {
+            if !pressed {
+                return Ok(());
+            }
+            let mut res = Ok(());
+            for handlers in self
+                .dev_states
+                .iter_mut()
+                .filter_map(|d| d.selected.as_mut())
+            {
+                res = res.and(handlers.panic());
+            }
+            return res;
+        }
+        if action == ButtonAction::NextPreset {
+            let presets = self.dev_states[i].presets.len();
+            if !pressed || presets == 0 {
+                return Ok(());
+            }
+            let next = self.dev_states[i].preset.map_or(0, |p| (p + 1) % presets);
+            return self.handle_msg(Msg::LoadPreset(i, next), gui_tx);
+        }
+        // the pedal goes to whichever port the notes are coming out of
+        if action == ButtonAction::Sustain {
+            return match self
+                .merged_into(i)
+                .and_then(|into| self.dev_states[into].selected.as_mut())
+            {
+                Some(handlers) if handlers.settings.hold == HoldMode::Pedal => {
+                    handlers.set_pedal(pressed)
+                }
+                Some(handlers) => handlers.midi_h.set_sustain(pressed),
+                None => Ok(()),
+            };
+        }
+        let Some(handlers) = self.dev_states[i].selected.as_mut() else {
+            return Ok(());
+        };
+        match action {
+            ButtonAction::Play => {
+                handlers.input_h.queue_press(pressed);
+                return Ok(());
+            }
+            _ if !pressed => return Ok(()),
+            ButtonAction::Latch => {
+                handlers.input_h.queue_toggle();
+                return Ok(());
+            }
+            ButtonAction::OctaveUp | ButtonAction::OctaveDown => {
+                let octaves = if action == ButtonAction::OctaveUp {
+                    1
+                } else {
+                    -1
+                };
+                handlers.transpose_by(octaves * OCTAVE);
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+            }
+            ButtonAction::Recenter => {
+                handlers.input_h.recenter();
+                let pos = handlers.input_h.float_pos();
+                gui_tx
+                    .positions
+                    .send_modify(|positions| positions[handlers.pos_idx] = pos);
+            }
+            ButtonAction::Sustain | ButtonAction::Panic | ButtonAction::NextPreset => {
+                unreachable!()
+            }
+        }
+        self.sync(i)
+    }
+
+    /// Does what the scrolled device's wheel is set to, velocity and CCs on whichever port its
+    /// notes are coming out of
+    fn handle_scroll(&mut self, i: usize, ticks: i32, gui_tx: &GuiTx) -> Result<()> {
+        let Some(handlers) = self.dev_states[i].selected.as_mut() else {
+            return Ok(());
+        };
+        let ticks = ticks.clamp(-MAX_TRANSPOSE as i32, MAX_TRANSPOSE as i32) as i8;
+        let action = handlers.settings.scroll;
+        let transposed = match action {
+            ScrollAction::Off => return Ok(()),
+            ScrollAction::Octave => {
+                let max_octaves = MAX_TRANSPOSE / OCTAVE;
+                handlers.transpose_by(ticks.clamp(-max_octaves, max_octaves) * OCTAVE);
+                true
+            }
+            ScrollAction::Transpose => {
+                handlers.transpose_by(ticks);
+                true
+            }
+            ScrollAction::Velocity | ScrollAction::Cc(_) => false,
+        };
+        let mut res = Ok(());
+        if transposed {
+            res = self.sync(i);
+        } else if let Some(into) = self
+            .merged_into(i)
+            .and_then(|into| self.dev_states[into].selected.as_mut())
+        {
+            let step = |value: u8, min: i32| {
+                (value as i32 + ticks as i32 * SCROLL_STEP).clamp(min, 0x7f) as u8
+            };
+            match action {
+                // no velocity at all would be a note off
+                ScrollAction::Velocity => into.set_velocity(step(into.midi_h.velocity, 1)),
+                ScrollAction::Cc(cc) => {
+                    into.scroll_cc_value = step(into.scroll_cc_value, 0);
+                    let value = into.scroll_cc_value as f32 / 0x7f as f32;
+                    res = into.midi_h.set_controller(Controller::Cc(cc), value);
+                }
+                _ => (),
+            }
+        }
+        gui_tx.devs.send_replace(gui_devices_from_states(
+            &self.dev_states,
+            &self.pairs,
+            &self.merges,
+            &self.learns,
+        ));
+        res
+    }
+
+    /// A volume hand only uses its Y axis, and only while the device it's paired with is
+    /// selected
+    fn handle_volume_event(&mut self, ev: manymouse::Event, p: usize) -> Result<()> {
+        let mapping = self.dev_states[ev.device as usize].settings.mapping;
+        let pair = &mut self.pairs[p];
+        let Some(pitch_h) = dev_index(&self.dev_states, &pair.pitch)
+            .and_then(|pitch| self.dev_states[pitch].selected.as_mut())
+        else {
+            return Ok(());
+        };
+        let input_h = &mut pair.volume_h;
+        input_h.mapping = mapping;
+        let relative = input_h.mapping == MappingMode::Relative;
+        let y = Axis::Y as u32;
+        match ev.ev_type {
+            manymouse::EventType::Relmotion if ev.item == y && relative => {
+                input_h.handle_rel_y(ev.value);
+            }
+            manymouse::EventType::Absmotion if ev.item == y && !relative => {
+                input_h.handle_abs_y(ev.value, ev.minval, ev.maxval);
+            }
+            _ => return Ok(()),
+        }
+        let volume = input_h.expression().timbre;
+        pitch_h.midi_h.set_controller(pair.controller, volume)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn poll_touchpads(&mut self, gui_tx: &GuiTx) -> Result<()> {
+        let mut res = Ok(());
+        for handlers in self
+            .dev_states
+            .iter_mut()
+            .filter_map(|d| d.selected.as_mut())
+        {
+            let Some(touchpad) = handlers.touchpad.as_mut() else {
+                continue;
+            };
+            let voice_evs = match touchpad.poll() {
+                Ok(voice_evs) => voice_evs,
+                Err(e) => {
+                    // most likely unplugged, stop polling it rather than erroring every loop
+                    handlers.touchpad = None;
+                    let _ = handlers.let_go_fingers();
+                    let _ = handlers.release_all();
+                    res = Err(e);
+                    continue;
+                }
+            };
+            if let Some(pos) = touchpad.float_pos() {
+                gui_tx
+                    .positions
+                    .send_modify(|positions| positions[handlers.pos_idx] = pos);
+            }
+            for voice_ev in voice_evs {
+                if let Err(e) = handlers.sync_finger(voice_ev) {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let clock = self.clock.as_ref().map(MidiClock::pos);
+        let mut res = Ok(());
+        for handlers in self
+            .dev_states
+            .iter_mut()
+            .filter_map(|d| d.selected.as_mut())
+        {
+            if let Err(e) = handlers.tick(now, clock) {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    /// Retunes the devices following the keyboard when different keys are held
+    fn follow_keys(&mut self, gui_tx: &GuiTx) -> Result<()> {
+        let Some(keys) = self.keys.as_mut().and_then(KeysIn::take_change) else {
+            return Ok(());
+        };
+        let mut res = Ok(());
+        for i in 0..self.dev_states.len() {
+            match self.dev_states[i].follow_keys(&keys) {
+                Ok(true) => res = res.and(self.sync(i)),
+                Ok(false) => (),
+                Err(e) => res = Err(e),
+            }
+        }
+        gui_tx.devs.send_replace(gui_devices_from_states(
+            &self.dev_states,
+            &self.pairs,
+            &self.merges,
+            &self.learns,
+        ));
+        res
+    }
+
+    /// Learns the first control pressed after a learn click, otherwise sends each control to
+    /// whatever it's bound to
+    fn handle_controls(&mut self, gui_tx: &GuiTx) -> Result<()> {
+        let Some(controls) = self.controls.as_ref() else {
+            return Ok(());
+        };
+        let events: Vec<ControlEvent> = controls.poll().collect();
+        let mut res = Ok(());
+        for ev in events {
+            // letting go of a switch learnt on its press isn't a second learn
+            if let Some((dev, target)) = self.learns.learning.take_if(|_| ev.down()) {
+                self.learns
+                    .learned
+                    .retain(|learned| learned.dev != dev || learned.target != target);
+                self.learns.learned.push(Learned {
+                    source: ev.source,
+                    dev,
+                    target,
+                });
+                gui_tx.devs.send_replace(gui_devices_from_states(
+                    &self.dev_states,
+                    &self.pairs,
+                    &self.merges,
+                    &self.learns,
+                ));
+                continue;
+            }
+            let bound: Vec<(usize, LearnTarget)> = self
+                .learns
+                .learned
+                .iter()
+                .filter(|learned| learned.source == ev.source)
+                .filter_map(|learned| {
+                    Some((dev_index(&self.dev_states, &learned.dev)?, learned.target))
+                })
+                .collect();
+            for (i, target) in bound {
+                res = res.and(self.handle_control(i, target, ev, gui_tx));
+            }
+        }
+        res
+    }
+
+    fn handle_control(
+        &mut self,
+        i: usize,
+        target: LearnTarget,
+        ev: ControlEvent,
+        gui_tx: &GuiTx,
+    ) -> Result<()> {
+        if let LearnTarget::Button(action) = target {
+            return self.handle_button(i, action, ev.down(), gui_tx);
+        }
+        // a note off would set everything to 0, and switches only act when pressed
+        let acts = match ev.source {
+            LearnSource::Note(_) => ev.down(),
+            LearnSource::Cc(_) => target.is_continuous() || ev.down(),
+        };
+        if !acts {
+            return Ok(());
+        }
+        if target == LearnTarget::NextScale {
+            let dev_state = &mut self.dev_states[i];
+            let scales = &dev_state.recent_scales;
+            if scales.is_empty() {
+                return Ok(());
+            }
+            let next = scales
+                .iter()
+                .position(|scale| Some(scale) == dev_state.scale.as_ref())
+                .map_or(0, |current| (current + 1) % scales.len());
+            let scale = scales[next].clone();
+            let kbm = dev_state.kbm.clone();
+            let keys = self.keys.as_ref().map_or(&[][..], KeysIn::last_held);
+            let res = dev_state.set_tuning_files(Some(scale), kbm, keys);
+            gui_tx.devs.send_replace(gui_devices_from_states(
+                &self.dev_states,
+                &self.pairs,
+                &self.merges,
+                &self.learns,
+            ));
+            return res;
+        }
+        if target == LearnTarget::Velocity {
+            if let Some(handlers) = self.dev_states[i].selected.as_mut() {
+                // no velocity at all would be a note off
+                handlers.set_velocity(ev.value.max(1));
+            }
+            gui_tx.devs.send_replace(gui_devices_from_states(
+                &self.dev_states,
+                &self.pairs,
+                &self.merges,
+                &self.learns,
+            ));
+            return Ok(());
+        }
+        let settings = self.dev_states[i].settings;
+        let value = ev.value;
+        let settings = match target {
+            LearnTarget::ToggleLegato => DevSettings {
+                legato: !settings.legato,
+                ..settings
+            },
+            LearnTarget::ToggleArp => DevSettings {
+                arp: match settings.arp {
+                    Some(_) => None,
+                    None => Some(self.dev_states[i].last_arp),
+                },
+                ..settings
+            },
+            LearnTarget::NextChord => DevSettings {
+                chord: match settings.chord {
+                    ChordMode::Off => ChordMode::Power,
+                    ChordMode::Power => ChordMode::Triad,
+                    ChordMode::Triad => ChordMode::Seventh,
+                    ChordMode::Seventh => self.dev_states[i]
+                        .fixed_chord
+                        .map_or(ChordMode::Off, ChordMode::Fixed),
+                    ChordMode::Fixed(_) => ChordMode::Off,
+                },
+                ..settings
+            },
+            LearnTarget::NextFollowKeys => DevSettings {
+                follow_keys: match settings.follow_keys {
+                    FollowKeys::Off => FollowKeys::Root,
+                    FollowKeys::Root => FollowKeys::Chord,
+                    FollowKeys::Chord => FollowKeys::Off,
+                },
+                ..settings
+            },
+            LearnTarget::Hysteresis => DevSettings {
+                // up to half a slot, the most the input handler takes
+                hysteresis: value as f32 / 0x7f as f32 * 0.5,
+                ..settings
+            },
+            LearnTarget::Sensitivity => DevSettings {
+                sensitivity: Sensitivity::from_7bit(value),
+                ..settings
+            },
+            LearnTarget::LowestKey => DevSettings {
+                range: NoteRange {
+                    lowest: value.min(settings.range.highest),
+                    ..settings.range
+                },
+                ..settings
+            },
+            LearnTarget::HighestKey => DevSettings {
+                range: NoteRange {
+                    highest: value.max(settings.range.lowest),
+                    ..settings.range
+                },
+                ..settings
+            },
+            LearnTarget::YControllerCc => DevSettings {
+                y_controller: Some(Controller::Cc(value)),
+                ..settings
+            },
+            LearnTarget::Glide => DevSettings {
+                portamento: (value > 0).then_some(value),
+                ..settings
+            },
+            LearnTarget::Button(_) | LearnTarget::NextScale | LearnTarget::Velocity => {
+                return Ok(())
+            }
+        };
+        if settings == self.dev_states[i].settings {
+            return Ok(());
+        }
+        self.handle_msg(Msg::SetDevSettings(i, settings), gui_tx)
+    }
+
+    fn flush_controllers(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        for handlers in self
+            .dev_states
+            .iter_mut()
+            .filter_map(|d| d.selected.as_mut())
+        {
+            if let Err(e) = handlers.midi_h.flush_controllers() {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    /// ManyMouse doesn't pass on SYN_REPORT, so each batch of polled events is treated as a
+    /// frame and presses are applied once all its motion has been. A touch's position can
+    /// still land in the next batch, so absolute presses also wait for it, see
+    /// `InputHandler::take_placed_presses`. Smoothed positions also catch up here, even in
+    /// frames without any motion
+    fn end_frame(&mut self, gui_tx: &GuiTx) -> Result<()> {
+        let mut res = Ok(());
+        for i in 0..self.dev_states.len() {
+            let Some(handlers) = self.dev_states[i].selected.as_mut() else {
+                continue;
+            };
+            let ticked = !handlers.is_polyphonic() && handlers.input_h.tick();
+            if ticked {
+                let pos = handlers.input_h.float_pos();
+                gui_tx
+                    .positions
+                    .send_modify(|positions| positions[handlers.pos_idx] = pos);
+            }
+            let presses = handlers.input_h.take_placed_presses(Instant::now());
+            if ticked {
+                if let Err(e) = self.sync(i) {
+                    res = Err(e);
+                }
+            }
+            for pressed in presses {
+                if let Some(handlers) = self.dev_states[i].selected.as_mut() {
+                    handlers.input_h.set_playing(pressed);
+                    handlers.pressed_at = pressed.then(Instant::now);
+                }
+                if let Err(e) = self.sync(i) {
+                    res = Err(e);
+                }
+            }
+        }
+        res
     }
 }
 
@@ -96,6 +1945,15 @@ pub fn use_theramin_msgs() -> Signal<TheraminMsgTx> {
     use_context()
 }
 
+pub fn use_theramin_errors() -> Signal<ErrorLog> {
+    use_context()
+}
+
+pub fn use_driver_name() -> Signal<DriverName> {
+    let driver_name_rx_context: Signal<DriverNameRx> = use_context();
+    use_update_context_by_rx(driver_name_rx_context)
+}
+
 pub fn use_theremin_positions() -> Signal<ThereminPositions> {
     let position_rx_context: Signal<ThereminPositionsRx> = use_context();
     use_update_context_by_rx(position_rx_context)
@@ -120,114 +1978,176 @@ pub fn use_theramin_routine() {
     let mut msg_tx_context = use_context_provider(|| {
         Signal::new(TheraminMsgTx {
             tx: mpsc::channel::<Msg>(1).0,
+            err_tx: mpsc::unbounded_channel().0,
         })
     });
     let mut devices_rx_context =
         use_context_provider(|| Signal::new(watch::channel(Devices::new()).1));
     let mut positions_rx_context =
         use_context_provider(|| Signal::new(watch::channel(ThereminPositions::new()).1));
+    let mut driver_name_rx_context =
+        use_context_provider(|| Signal::new(watch::channel(DriverName::None).1));
 
     use_context_provider(|| Signal::new(Devices::new()));
     use_context_provider(|| Signal::new(ThereminPositions::new()));
+    use_context_provider(|| Signal::new(DriverName::None));
+    let mut errors = use_context_provider(|| Signal::new(ErrorLog::new()));
 
     use_hook(|| {
         // init with real channels
+        let (err_tx, mut err_rx) = mpsc::unbounded_channel();
+        spawn(async move {
+            while let Some(err) = err_rx.recv().await {
+                let mut log = errors.write();
+                // a dead port fails on every event, so repeats are counted instead of listed
+                match log.last_mut() {
+                    Some((last, times)) if *last == err => *times += 1,
+                    _ => {
+                        eprintln!("{}", err);
+                        log.push((err, 1));
+                    }
+                }
+                let excess = log.len().saturating_sub(MAX_LOGGED_ERRORS);
+                log.drain(..excess);
+            }
+        });
         let (msg_tx, mut msg_rx) = mpsc::channel(MSG_BUFF_SIZE);
-        *msg_tx_context.write() = TheraminMsgTx { tx: msg_tx };
+        *msg_tx_context.write() = TheraminMsgTx {
+            tx: msg_tx,
+            err_tx: err_tx.clone(),
+        };
         let (devs_tx, devs_rx) = watch::channel(Devices::new());
         *devices_rx_context.write() = devs_rx;
         let (pos_tx, pos_rx) = watch::channel(ThereminPositions::new());
         *positions_rx_context.write() = pos_rx;
+        let (driver_name_tx, driver_name_rx) = watch::channel(DriverName::None);
+        *driver_name_rx_context.write() = driver_name_rx;
+        let gui_tx = GuiTx {
+            devs: devs_tx,
+            positions: pos_tx,
+            driver_name: driver_name_tx,
+        };
 
         tokio::spawn(async move {
-            let mut s = State::new(&devs_tx, &pos_tx);
+            let report = |res: Result<()>| {
+                if let Err(e) = res {
+                    let _ = err_tx.send(e);
+                }
+            };
+            let (mut s, res) = State::new(&gui_tx);
+            report(res);
             'main_loop: loop {
                 use mpsc::error::TryRecvError;
                 loop {
                     match msg_rx.try_recv() {
                         Err(TryRecvError::Empty) => break,
                         Err(TryRecvError::Disconnected) => break 'main_loop,
-                        Ok(msg) => {
-                            match msg {
-                                Msg::FindNewDevices => {
-                                    s.dev_states
-                                        .iter_mut()
-                                        .filter_map(|d| d.selected.take().map(|hs| hs.midi_h))
-                                        .for_each(|m_h| {
-                                            m_h.close();
-                                        });
-                                    drop(s.m_mouse);
-                                    s = State::new(&devs_tx, &pos_tx);
-                                }
-                                Msg::ClickDev(i) => {
-                                    if s.dev_states[i].selected.is_some() {
-                                        s.dev_states[i].selected.take().unwrap().midi_h.close();
-                                    } else {
-                                        s.dev_states[i].selected = Some(DevHandlers {
-                                            pos_idx: 0,
-                                            input_h: InputHandler::new(DEFAULT_NOTE_WIDTH),
-                                            midi_h: MidiInitialiser::new()
-                                                .virtual_port(&s.dev_states[i].name),
-                                        });
-                                    }
-                                    // update pos_idxs
-                                    s.dev_states
-                                        .iter_mut()
-                                        .filter_map(|d| d.selected.as_mut())
-                                        .enumerate()
-                                        .for_each(|(i, selected)| selected.pos_idx = i);
-                                    // update device list
-                                    devs_tx
-                                        .send(gui_devices_from_states(&s.dev_states))
-                                        .unwrap();
-                                    // update length of pos array
-                                    let new_len = s
-                                        .dev_states
-                                        .iter()
-                                        .filter(|d| d.selected.is_some())
-                                        .count();
-                                    pos_tx.send_modify(|positions| positions.resize(new_len, 0.0));
-                                }
-                            }
-                        }
+                        Ok(msg) => report(s.handle_msg(msg, &gui_tx)),
                     }
                 }
                 // TODO detect when device disconnected
-                for ev in s.m_mouse.poll() {
-                    if s.dev_states[ev.device as usize].selected.is_none() {
-                        continue;
-                    }
-                    match ev.ev_type {
-                        manymouse::EventType::Relmotion if ev.item == Axis::X as u32 => {
-                            let i = ev.device as usize;
-                            let handlers = s.dev_states[i].selected.as_mut().unwrap();
-                            let input_h = &mut handlers.input_h;
-                            let midi_h = &mut handlers.midi_h;
-                            let pitch = input_h.handle_rel_move(ev.value);
-                            if input_h.playing {
-                                midi_h.play(pitch);
-                            }
-                            pos_tx.send_modify(|positions| {
-                                positions[handlers.pos_idx] = input_h.float_pos()
-                            });
-                        }
-                        manymouse::EventType::Button if ev.item == Button::LMB as u32 => {
-                            let i = ev.device as usize;
-                            let handlers = s.dev_states[i].selected.as_mut().unwrap();
-                            let input_h = &mut handlers.input_h;
-                            let midi_h = &mut handlers.midi_h;
-                            input_h.playing = ev.value == 1;
-                            if input_h.playing {
-                                midi_h.play(input_h.pitch_from_pos());
-                            } else {
-                                midi_h.release();
-                            }
-                        }
-                        _ => (),
-                    }
+                let events: Vec<_> = match s.m_mouse.as_mut() {
+                    Some(m_mouse) => m_mouse.poll().collect(),
+                    None => Vec::new(),
+                };
+                for ev in events {
+                    report(s.handle_event(ev, &gui_tx));
                 }
+                report(s.end_frame(&gui_tx));
+                report(s.tick());
+                report(s.follow_keys(&gui_tx));
+                report(s.handle_controls(&gui_tx));
+                report(s.flush_controllers());
+                #[cfg(target_os = "linux")]
+                report(s.poll_touchpads(&gui_tx));
             }
         });
     });
     use_update_context_by_rx(devices_rx_context);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINOR_SCL: &str = "! minor.scl
+Natural minor
+ 7
+ 200.0
+ 300.0
+ 500.0
+ 700.0
+ 800.0
+ 1000.0
+ 2/1
+";
+
+    const AUGMENTED_SCL: &str = "! augmented.scl
+Major thirds
+ 3
+ 400.0
+ 800.0
+ 2/1
+";
+
+    /// Semitones above the root of the chord played on `key`
+    fn chord_intervals(scale: &Scale, chord_mode: ChordMode, key: Pitch) -> Vec<i16> {
+        let tuning = Tuning::new(scale, &KeyboardMap::default()).unwrap();
+        let settings = DevSettings {
+            chord: chord_mode,
+            ..DevSettings::default()
+        };
+        let slot = tuning
+            .slots()
+            .iter()
+            .position(|slot| slot.key == key)
+            .unwrap();
+        let bar_pos = BarPos {
+            slot,
+            pos: slot as f32 + 0.5,
+        };
+        let notes = chord(&tuning, &tuning, &settings, bar_pos, 0);
+        notes
+            .iter()
+            .map(|&(note, _)| note as i16 - notes[0].0 as i16)
+            .collect()
+    }
+
+    #[test]
+    fn twelve_tone_chords_are_major() {
+        let scale = Scale::default();
+        assert_eq!(chord_intervals(&scale, ChordMode::Triad, 60), [0, 4, 7]);
+        assert_eq!(
+            chord_intervals(&scale, ChordMode::Seventh, 60),
+            [0, 4, 7, 11]
+        );
+    }
+
+    #[test]
+    fn seven_note_scale_chords_follow_the_scale() {
+        let minor = Scale::parse(MINOR_SCL).unwrap();
+        // the key with degree 0, where the scale starts
+        assert_eq!(chord_intervals(&minor, ChordMode::Triad, 60), [0, 3, 7]);
+        assert_eq!(
+            chord_intervals(&minor, ChordMode::Seventh, 60),
+            [0, 3, 7, 10]
+        );
+    }
+
+    #[test]
+    fn scale_shorter_than_the_chord_falls_back_to_major() {
+        let augmented = Scale::parse(AUGMENTED_SCL).unwrap();
+        assert_eq!(
+            chord_intervals(&augmented, ChordMode::Seventh, 60),
+            [0, 4, 7, 11]
+        );
+    }
+
+    #[test]
+    fn transpose_stops_at_the_limit() {
+        assert_eq!(transposed(MAX_TRANSPOSE, 7 * OCTAVE), MAX_TRANSPOSE);
+        assert_eq!(transposed(MAX_TRANSPOSE, i8::MAX), MAX_TRANSPOSE);
+        assert_eq!(transposed(-MAX_TRANSPOSE, i8::MIN), -MAX_TRANSPOSE);
+        assert_eq!(transposed(MAX_TRANSPOSE, -OCTAVE), MAX_TRANSPOSE - OCTAVE);
+    }
+}